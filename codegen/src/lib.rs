@@ -0,0 +1,206 @@
+//! Procedural macros for rocket-gssapi, re-exported by its `macros` feature.
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote};
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Expr, ExprLit, FnArg, ItemFn, Lit, LitStr, MetaNameValue, Token};
+
+/// Requires the client of a route to be authenticated with a principal meeting the given
+/// requirements, responding with `403 Forbidden` otherwise.
+///
+/// ```rust,ignore
+/// #[gssapi_require(realm = "CORP.EXAMPLE", principal = "alice@CORP.EXAMPLE")]
+/// #[get("/admin")]
+/// async fn admin() -> &'static str {
+///     "Hello admin!"
+/// }
+/// ```
+///
+/// `realm` takes a realm or `*.` suffix pattern, `principal` a full principal name and `group`
+/// a group SID, matched against the PAC, or a group name for the group resolver. Each may be
+/// given several times, in which case any of the values is accepted. If several kinds are
+/// given, all must be met. The attribute must be placed above the route attribute.
+#[proc_macro_attribute]
+pub fn gssapi_require(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args with Punctuated::<MetaNameValue, Token![,]>::parse_terminated);
+    let mut function = parse_macro_input!(input as ItemFn);
+
+    match expand(args, &mut function) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(
+    args: Punctuated<MetaNameValue, Token![,]>,
+    function: &mut ItemFn,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let mut realms = Vec::new();
+    let mut principals = Vec::new();
+    let mut groups = Vec::new();
+
+    for arg in &args {
+        let value = match &arg.value {
+            Expr::Lit(ExprLit {
+                lit: Lit::Str(s), ..
+            }) => s,
+            v => return Err(syn::Error::new(v.span(), "expected a string literal")),
+        };
+        let key = arg.path.get_ident().map(|i| i.to_string()).unwrap_or_default();
+        match key.as_str() {
+            "realm" => {
+                validate_realm(value)?;
+                realms.push(value.clone());
+            }
+            "principal" => {
+                validate_principal(value)?;
+                principals.push(value.clone());
+            }
+            "group" => {
+                if value.value().is_empty() {
+                    return Err(syn::Error::new(value.span(), "group must not be empty"));
+                }
+                groups.push(value.clone());
+            }
+            _ => {
+                return Err(syn::Error::new(
+                    arg.path.span(),
+                    "unknown requirement, expected `realm`, `principal` or `group`",
+                ))
+            }
+        }
+    }
+    if realms.is_empty() && principals.is_empty() && groups.is_empty() {
+        return Err(syn::Error::new(
+            Span::call_site(),
+            "expected at least one requirement, e.g. `realm = \"EXAMPLE.COM\"`",
+        ));
+    }
+
+    let guard = format_ident!("__GssapiRequire_{}", function.sig.ident);
+    let param: FnArg = syn::parse_quote!(_gssapi_require: #guard);
+    function.sig.inputs.push(param);
+    let vis = &function.vis;
+
+    Ok(quote! {
+        #[doc(hidden)]
+        #[allow(non_camel_case_types)]
+        #vis struct #guard;
+
+        #[::rocket::async_trait]
+        impl<'r> ::rocket::request::FromRequest<'r> for #guard {
+            type Error = ::rocket_gssapi::GssapiError;
+
+            async fn from_request(
+                req: &'r ::rocket::Request<'_>,
+            ) -> ::rocket::request::Outcome<Self, Self::Error> {
+                const REQUIREMENTS: ::rocket_gssapi::require::Requirements =
+                    ::rocket_gssapi::require::Requirements {
+                        realms: &[#(#realms),*],
+                        principals: &[#(#principals),*],
+                        groups: &[#(#groups),*],
+                    };
+                REQUIREMENTS.check(req).await.map(|_| #guard)
+            }
+        }
+
+        #function
+    })
+}
+
+/// Realms are matched as given or as `*.` suffix patterns, and can't contain name separators.
+fn validate_realm(realm: &LitStr) -> syn::Result<()> {
+    let value = realm.value();
+    let name = value.strip_prefix("*.").unwrap_or(&value);
+    if name.is_empty() || value == "*." {
+        Err(syn::Error::new(realm.span(), "realm must not be empty"))
+    } else if name.contains(['@', '/', '*']) {
+        Err(syn::Error::new(
+            realm.span(),
+            "realm must be a realm name or a `*.` suffix pattern",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Principals must be complete, with a non-empty primary component and realm.
+fn validate_principal(principal: &LitStr) -> syn::Result<()> {
+    let value = principal.value();
+    let mut escaped = false;
+    let mut at = None;
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '@' if at.is_some() => {
+                return Err(syn::Error::new(principal.span(), "principal has multiple realms"))
+            }
+            '@' => at = Some(i),
+            _ => {}
+        }
+    }
+    match at {
+        Some(i) if i > 0 && i + 1 < value.len() && !escaped => Ok(()),
+        _ => Err(syn::Error::new(
+            principal.span(),
+            "expected a principal such as `alice@EXAMPLE.COM`",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_with(args: proc_macro2::TokenStream) -> syn::Result<String> {
+        let args = syn::parse::Parser::parse2(
+            Punctuated::<MetaNameValue, Token![,]>::parse_terminated,
+            args,
+        )?;
+        let mut function: ItemFn = syn::parse_quote!(
+            async fn admin() -> &'static str {
+                "Hello admin!"
+            }
+        );
+        expand(args, &mut function).map(|tokens| tokens.to_string())
+    }
+
+    fn error(args: proc_macro2::TokenStream) -> String {
+        expand_with(args).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn emits_requirements_and_guard() {
+        let tokens = expand_with(quote!(
+            realm = "*.CORP.EXAMPLE",
+            principal = "alice@CORP.EXAMPLE",
+            group = "admins"
+        ))
+        .unwrap();
+        assert!(tokens.contains("realms : & [\"*.CORP.EXAMPLE\"]"));
+        assert!(tokens.contains("principals : & [\"alice@CORP.EXAMPLE\"]"));
+        assert!(tokens.contains("groups : & [\"admins\"]"));
+        assert!(tokens.contains("_gssapi_require : __GssapiRequire_admin"));
+    }
+
+    #[test]
+    fn requires_a_requirement() {
+        assert!(error(quote!()).contains("at least one requirement"));
+        assert!(error(quote!(user = "alice")).contains("unknown requirement"));
+        assert!(error(quote!(realm = 1)).contains("string literal"));
+    }
+
+    #[test]
+    fn validates_values() {
+        assert!(error(quote!(realm = "")).contains("must not be empty"));
+        assert!(error(quote!(realm = "*.")).contains("must not be empty"));
+        assert!(error(quote!(realm = "alice@CORP")).contains("suffix pattern"));
+        assert!(error(quote!(principal = "alice")).contains("expected a principal"));
+        assert!(error(quote!(principal = "@CORP")).contains("expected a principal"));
+        assert!(error(quote!(principal = "a@B@C")).contains("multiple realms"));
+        assert!(expand_with(quote!(principal = "a\\@b@CORP")).is_ok());
+        assert!(error(quote!(group = "")).contains("must not be empty"));
+    }
+}
@@ -0,0 +1,34 @@
+use rocket::{get, launch, routes};
+use rocket_gssapi::{gssapi_require, FileGroupResolver, Group, GssapiFairing, InGroup};
+
+/// This example shows how to restrict routes to members of a group. Groups come from the PAC
+/// of tickets issued by Active Directory, and from `groups.toml` for other clients.
+
+struct Admins;
+impl Group for Admins {
+    const NAME: &'static str = "admins";
+    const SID: Option<&'static str> = Some("S-1-5-21-1004336348-1177238915-682003330-512");
+}
+
+#[launch]
+async fn rocket() -> _ {
+    rocket::build()
+        .attach(
+            GssapiFairing::builder()
+                .group_resolver(FileGroupResolver::load("groups.toml").expect("Invalid groups file"))
+                .build()
+                .expect("Invalid GSSAPI configuration"),
+        )
+        .mount("/", routes![admin, operators])
+}
+
+#[get("/admin")]
+async fn admin(admin: InGroup<Admins>) -> String {
+    format!("Hello admin {:?}!", admin.source)
+}
+
+#[gssapi_require(group = "operators")]
+#[get("/operators")]
+async fn operators() -> &'static str {
+    "Hello operator!"
+}
@@ -1,4 +1,3 @@
-use libgssapi::credential::CredUsage;
 use rocket::{get, launch, routes, Request};
 use rocket::http::Status;
 use rocket::request::{FromRequest, Outcome};
@@ -23,7 +22,13 @@ async fn rocket() -> _ {
     desired_mechs.add(&GSS_MECH_KRB5).expect("Failed to add OID");
 
     rocket::build()
-        .attach(GssapiFairing::new(Some(name), Some(desired_mechs), CredUsage::Accept))
+        .attach(
+            GssapiFairing::builder()
+                .principal(name)
+                .mechanisms(desired_mechs)
+                .build()
+                .expect("Invalid GSSAPI configuration"),
+        )
         .mount("/", routes![secure_index])
 }
 
@@ -36,10 +41,10 @@ impl<'r> FromRequest<'r> for User {
     type Error = ();
 
     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        if let Outcome::Success(g) = req.guard::<GssapiAuth>().await {
+        if let Outcome::Success(GssapiAuth { principal: Some(p), .. }) = req.guard::<GssapiAuth>().await {
             let u = User {
-                name: g.source.clone().unwrap().split_once('@').unwrap().0.to_string(),
-                mail: g.source.unwrap().to_lowercase(),
+                name: p.username().to_string(),
+                mail: format!("{}@{}", p.username(), p.realm().unwrap_or_default()).to_lowercase(),
             };
             Outcome::Success(u)
         } else {
@@ -0,0 +1,24 @@
+use rocket::{get, launch, routes};
+use rocket_gssapi::{gssapi_require, GssapiFairing};
+
+/// This example shows how to restrict routes to certain realms and principals, without
+/// writing a request guard for every check.
+
+#[launch]
+async fn rocket() -> _ {
+    rocket::build()
+        .attach(GssapiFairing::fairing())
+        .mount("/", routes![index, admin])
+}
+
+#[gssapi_require(realm = "EXAMPLE.COM", realm = "*.EXAMPLE.COM")]
+#[get("/")]
+async fn index() -> &'static str {
+    "Hello colleague!"
+}
+
+#[gssapi_require(principal = "alice@EXAMPLE.COM", principal = "bob/admin@EXAMPLE.COM")]
+#[get("/admin")]
+async fn admin() -> &'static str {
+    "Hello admin!"
+}
@@ -0,0 +1,305 @@
+use crate::ffi::{GSS_C_INDEFINITE, GSS_S_CONTINUE_NEEDED};
+use libgssapi::context::CtxFlags;
+use libgssapi::credential::Cred;
+use libgssapi::error::{Error, MajorFlags};
+use libgssapi::name::Name;
+use libgssapi::oid::{Oid, GSS_MECH_IAKERB, GSS_MECH_KRB5, GSS_MECH_SPNEGO};
+use libgssapi_sys::{
+    gss_OID, gss_accept_sec_context, gss_buffer_desc, gss_ctx_id_t, gss_delete_sec_context,
+    gss_inquire_context, gss_release_buffer, gss_release_name, OM_uint32,
+};
+use std::ptr;
+use std::time::Duration;
+
+/// Whether a major status has a routine or calling error set, as opposed to only
+/// supplementary information such as `GSS_S_CONTINUE_NEEDED`.
+fn is_error(major: OM_uint32) -> bool {
+    major & 0xffff0000 != 0
+}
+
+/// A failed step of an [`AcceptCtx`], with the output token the mechanism produced for the
+/// client, such as a `KRB-ERROR` telling it its clock is skewed.
+#[derive(Debug)]
+pub(crate) struct AcceptError {
+    pub error: Error,
+    pub token: Option<Vec<u8>>,
+}
+
+/// An acceptor security context, working `gss_accept_sec_context` directly.
+///
+/// Unlike `ServerCtx::step`, a failing step returns the output token along with the error, so
+/// the client learns why it was rejected without the token being accepted a second time,
+/// which Kerberos would refuse as a replay.
+pub struct AcceptCtx {
+    ctx: gss_ctx_id_t,
+    cred: Option<Cred>,
+    source_name: Option<Name>,
+    mechanism: Option<&'static Oid>,
+    flags: CtxFlags,
+    lifetime: Option<Duration>,
+    delegated_cred: Option<Cred>,
+    complete: bool,
+}
+
+// GSSAPI contexts may be used from any thread, one at a time. Not `Sync`, as `&self` methods
+// pass the handle to GSSAPI as well; stores keep contexts behind a lock instead.
+unsafe impl Send for AcceptCtx {}
+
+impl AcceptCtx {
+    /// Creates a context accepting with `cred`, or with the default acceptor credentials.
+    pub fn new(cred: Option<Cred>) -> AcceptCtx {
+        AcceptCtx {
+            ctx: ptr::null_mut(),
+            cred,
+            source_name: None,
+            mechanism: None,
+            flags: CtxFlags::empty(),
+            lifetime: None,
+            delegated_cred: None,
+            complete: false,
+        }
+    }
+
+    /// Whether the negotiation has completed and the client is authenticated.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Accepts the next token of the client, returning the token to send back, if any.
+    pub(crate) fn step(&mut self, token: &[u8]) -> Result<Option<Vec<u8>>, AcceptError> {
+        let mut minor = 0;
+        let mut input = gss_buffer_desc {
+            length: token.len(),
+            value: token.as_ptr() as *mut _,
+        };
+        let mut output = gss_buffer_desc {
+            length: 0,
+            value: ptr::null_mut(),
+        };
+        let mut source_name = ptr::null_mut();
+        let mut mech_type: gss_OID = ptr::null_mut();
+        let mut flags = 0;
+        let mut time_rec = 0;
+        let mut delegated_cred = ptr::null_mut();
+        let major = unsafe {
+            gss_accept_sec_context(
+                &mut minor,
+                &mut self.ctx,
+                self.cred.as_ref().map_or(ptr::null_mut(), |c| c.to_c()),
+                &mut input,
+                ptr::null_mut(),
+                &mut source_name,
+                &mut mech_type,
+                &mut output,
+                &mut flags,
+                &mut time_rec,
+                &mut delegated_cred,
+            )
+        };
+
+        let token = if output.length > 0 && !output.value.is_null() {
+            let bytes =
+                unsafe { std::slice::from_raw_parts(output.value as *const u8, output.length) };
+            Some(bytes.to_vec())
+        } else {
+            None
+        };
+        unsafe { gss_release_buffer(&mut minor, &mut output) };
+
+        if is_error(major) {
+            unsafe {
+                if !source_name.is_null() {
+                    gss_release_name(&mut minor, &mut source_name);
+                }
+            }
+            return Err(AcceptError {
+                error: Error {
+                    major: MajorFlags::from_bits_truncate(major),
+                    minor,
+                },
+                token,
+            });
+        }
+
+        if !source_name.is_null() {
+            self.source_name = Some(unsafe { Name::from_c(source_name) });
+        }
+        if !delegated_cred.is_null() {
+            self.delegated_cred = Some(unsafe { Cred::from_c(delegated_cred) });
+        }
+        self.mechanism = known_mech(mech_type);
+        self.flags = CtxFlags::from_bits_truncate(flags);
+        self.lifetime =
+            (time_rec != GSS_C_INDEFINITE).then(|| Duration::from_secs(time_rec as u64));
+        self.complete = major & GSS_S_CONTINUE_NEEDED == 0;
+        Ok(token)
+    }
+
+    /// The authenticated client, once the context is complete.
+    pub(crate) fn take_source_name(&mut self) -> Option<Name> {
+        self.source_name.take()
+    }
+
+    /// The service principal the client authenticated to.
+    pub(crate) fn target_name(&self) -> Option<Name> {
+        if self.ctx.is_null() {
+            return None;
+        }
+        let mut minor = 0;
+        let mut target = ptr::null_mut();
+        let major = unsafe {
+            gss_inquire_context(
+                &mut minor,
+                self.ctx,
+                ptr::null_mut(),
+                &mut target,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+        if is_error(major) || target.is_null() {
+            None
+        } else {
+            Some(unsafe { Name::from_c(target) })
+        }
+    }
+
+    pub(crate) fn mechanism(&self) -> Option<&'static Oid> {
+        self.mechanism
+    }
+
+    pub(crate) fn flags(&self) -> CtxFlags {
+        self.flags
+    }
+
+    /// How long the context is valid for, as of completing it.
+    pub(crate) fn lifetime(&self) -> Option<Duration> {
+        self.lifetime
+    }
+
+    pub(crate) fn take_delegated_cred(&mut self) -> Option<Cred> {
+        self.delegated_cred.take()
+    }
+}
+
+impl Drop for AcceptCtx {
+    fn drop(&mut self) {
+        if !self.ctx.is_null() {
+            let mut minor = 0;
+            unsafe { gss_delete_sec_context(&mut minor, &mut self.ctx, ptr::null_mut()) };
+        }
+    }
+}
+
+/// The libgssapi constant of a mechanism returned by GSSAPI, if it is one the fairing can be
+/// configured with.
+fn known_mech(mech: gss_OID) -> Option<&'static Oid> {
+    if mech.is_null() {
+        return None;
+    }
+    let der = unsafe {
+        std::slice::from_raw_parts((*mech).elements as *const u8, (*mech).length as usize)
+    };
+    [&GSS_MECH_KRB5, &GSS_MECH_SPNEGO, &GSS_MECH_IAKERB]
+        .into_iter()
+        .find(|m| ***m == *der)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cred::CredCache;
+    use crate::der::{self, tlv};
+    use crate::keytab::tests::{encode, Entry};
+    use crate::krb5::tests::{ap_req, gss_token};
+    use crate::spnego::{NegState, NegToken, SPNEGO_OID};
+    use crate::token::{KRB5_OID, NTLMSSP_OID};
+    use libgssapi::credential::CredUsage;
+    use libgssapi_sys::gss_OID_desc;
+    use std::fs;
+
+    /// Steps a new context accepting with a keytab for `HTTP/web@CORP`, whose key doesn't
+    /// decrypt the dummy tickets of `ap_req`.
+    fn step(name: &str, token: &[u8]) -> Result<Option<Vec<u8>>, AcceptError> {
+        let path = std::env::temp_dir().join(format!(
+            "rocket-gssapi-accept-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let keytab = encode(2, false, &[Entry::new(&["HTTP", "web", "CORP"], 7, 17)]);
+        fs::write(&path, keytab).unwrap();
+        let mut creds = CredCache::new(None, None, CredUsage::Accept);
+        creds.set_store(Some(path.clone()), None).unwrap();
+        let mut ctx = AcceptCtx::new(Some(creds.acquire().unwrap()));
+        // MIT reads the keytab when accepting, not when acquiring
+        let stepped = ctx.step(token);
+        fs::remove_file(&path).unwrap();
+        assert!(!ctx.is_complete());
+        stepped
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        let mut ctx = AcceptCtx::new(None);
+        let e = ctx.step(b"garbage").unwrap_err();
+        assert_eq!(
+            e.error.major.bits(),
+            MajorFlags::GSS_S_DEFECTIVE_TOKEN.bits()
+        );
+        assert!(!ctx.is_complete());
+    }
+
+    #[test]
+    fn rejects_neg_token_resp_on_a_new_context() {
+        // accept-incomplete, as if continuing a negotiation
+        let resp = tlv(0xa1, &tlv(0x30, &tlv(0xa0, &[0x0a, 0x01, 0x01])));
+        let e = step("resp", &resp).unwrap_err();
+        assert_eq!(
+            e.error.major.bits(),
+            MajorFlags::GSS_S_DEFECTIVE_TOKEN.bits()
+        );
+    }
+
+    #[test]
+    fn keeps_the_krb_error_of_a_failed_step() {
+        let token = gss_token(KRB5_OID, &ap_req(17, Some(&[7])));
+        let e = step("krb5", &token).unwrap_err();
+        let token = e.token.expect("no error token");
+        // The KRB-ERROR, with its GSSAPI header and token ID
+        let (t, _) = der::expect(&token, 0x60).unwrap();
+        let (oid, rest) = der::expect(t, 0x06).unwrap();
+        assert_eq!(oid, KRB5_OID);
+        assert_eq!(rest[..3], [0x03, 0x00, 0x7e]);
+    }
+
+    #[test]
+    fn keeps_the_spnego_reject_of_a_failed_step() {
+        let mech_types = tlv(0xa0, &tlv(0x30, &tlv(0x06, NTLMSSP_OID)));
+        let init = tlv(0xa0, &tlv(0x30, &mech_types));
+        let token = tlv(0x60, &[tlv(0x06, SPNEGO_OID), init].concat());
+        let e = step("spnego", &token).unwrap_err();
+        match NegToken::parse(&e.token.expect("no error token")) {
+            Ok(NegToken::Resp(resp)) => assert_eq!(resp.neg_state, Some(NegState::Reject)),
+            t => panic!("not a NegTokenResp: {:?}", t),
+        }
+    }
+
+    #[test]
+    fn maps_known_mechanisms() {
+        let known = |der: &[u8]| {
+            let mut oid = gss_OID_desc {
+                length: der.len() as u32,
+                elements: der.as_ptr() as *mut _,
+            };
+            known_mech(&mut oid)
+        };
+        for mech in [&GSS_MECH_KRB5, &GSS_MECH_SPNEGO, &GSS_MECH_IAKERB] {
+            assert!(known(mech).is_some_and(|m| ptr::eq(m, mech)));
+        }
+        assert!(known(NTLMSSP_OID).is_none());
+        assert!(known_mech(ptr::null_mut()).is_none());
+    }
+}
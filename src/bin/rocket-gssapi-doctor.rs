@@ -0,0 +1,388 @@
+//! Checks a server's Kerberos setup the way the fairing would see it, printing a pass/fail
+//! report.
+//!
+//! ```text
+//! rocket-gssapi-doctor [--negotiate <header>]
+//! ```
+//!
+//! The `gssapi` section is read from `Rocket.toml` and `ROCKET_` environment variables of the
+//! current profile, as selected by `ROCKET_PROFILE`. A `WWW-Authenticate` or `Authorization`
+//! header pasted with `--negotiate`, or `-` to read it from stdin, is decoded and compared
+//! with the keytab.
+use base64::prelude::*;
+use rocket_gssapi::name::Name;
+use rocket_gssapi::oid::GSS_MECH_KRB5;
+use rocket_gssapi::{ApReq, GssapiConfig, GssapiFairing, Keytab, NegToken, SpnegoError, TokenKind};
+use std::io::{self, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: rocket-gssapi-doctor [--negotiate <header> | --negotiate -]";
+
+#[derive(Default)]
+struct Report {
+    failures: usize,
+    warnings: usize,
+}
+impl Report {
+    fn pass(&mut self, check: &str, detail: impl AsRef<str>) {
+        println!("[PASS] {}: {}", check, detail.as_ref());
+    }
+
+    fn warn(&mut self, check: &str, detail: impl AsRef<str>) {
+        self.warnings += 1;
+        println!("[WARN] {}: {}", check, detail.as_ref());
+    }
+
+    fn fail(&mut self, check: &str, detail: impl AsRef<str>) {
+        self.failures += 1;
+        println!("[FAIL] {}: {}", check, detail.as_ref());
+    }
+
+    fn succeeded(&self) -> bool {
+        self.failures == 0
+    }
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let mut negotiate = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--negotiate" => match args.next() {
+                Some(h) => negotiate = Some(h),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return ExitCode::from(2);
+                }
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return ExitCode::SUCCESS;
+            }
+            _ => {
+                eprintln!("{}", USAGE);
+                return ExitCode::from(2);
+            }
+        }
+    }
+
+    let mut report = Report::default();
+    let config = match load_config(&mut report) {
+        Some(c) => c,
+        None => return summarize(&report),
+    };
+    let krb5_config = std::env::var("KRB5_CONFIG").unwrap_or_else(|_| "/etc/krb5.conf".to_string());
+    check_krb5_conf(&mut report, &krb5_config);
+    let keytab = check_keytab(&mut report, &config);
+    check_service_name(&mut report, &config);
+    check_credentials(&mut report, &config);
+    if let Some(header) = negotiate {
+        check_token(&mut report, &header, keytab.as_ref());
+    }
+    summarize(&report)
+}
+
+fn summarize(report: &Report) -> ExitCode {
+    println!();
+    println!("{} failed, {} warnings", report.failures, report.warnings);
+    if report.succeeded() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Extracts the `gssapi` section like [`GssapiFairing::fairing()`] does.
+fn load_config(report: &mut Report) -> Option<GssapiConfig> {
+    let figment = rocket::Config::figment();
+    let profile = figment.profile().clone();
+    match figment.extract_inner::<GssapiConfig>("gssapi") {
+        Ok(c) => {
+            let principal = c.principal.as_deref().unwrap_or("system default");
+            report.pass("configuration", format!("profile {}, principal {}", profile, principal));
+            Some(c)
+        }
+        Err(e) if e.missing() => {
+            report.warn("configuration", format!("no gssapi section in profile {}", profile));
+            Some(GssapiConfig::default())
+        }
+        Err(e) => {
+            report.fail("configuration", e.to_string());
+            None
+        }
+    }
+}
+
+/// Reads the files of the colon-separated `KRB5_CONFIG` list. Like MIT Kerberos, unreadable
+/// files are skipped, so only failing to read all of them is a failure.
+fn check_krb5_conf(report: &mut Report, paths: &str) {
+    let mut read = false;
+    let mut realm = false;
+    for path in paths.split(':').filter(|p| !p.is_empty()) {
+        match std::fs::read_to_string(path) {
+            Ok(conf) => {
+                read = true;
+                match default_realm(&conf) {
+                    Some(r) => {
+                        realm = true;
+                        report.pass("krb5.conf", format!("{} (default realm {})", path, r))
+                    }
+                    None => report.pass("krb5.conf", path),
+                }
+            }
+            Err(e) => report.warn("krb5.conf", format!("{}: {}, skipped", path, e)),
+        }
+    }
+    if !read {
+        report.fail("krb5.conf", format!("none of {} is readable", paths));
+    } else if !realm {
+        report.warn("krb5.conf", "no default_realm is set");
+    }
+}
+
+/// The `default_realm` of the `[libdefaults]` section of a krb5.conf.
+fn default_realm(conf: &str) -> Option<&str> {
+    let mut libdefaults = false;
+    for line in conf.lines().map(str::trim) {
+        if let Some(section) = line.strip_prefix('[') {
+            libdefaults = section.trim_end_matches(']').trim() == "libdefaults";
+        } else if let Some(value) = line.strip_prefix("default_realm") {
+            match value.trim_start().strip_prefix('=') {
+                Some(realm) if libdefaults => return Some(realm.trim()),
+                _ => {}
+            }
+        }
+    }
+    None
+}
+
+/// Lists the keys of the keytab and checks that it is readable by us but not by everyone.
+fn check_keytab(report: &mut Report, config: &GssapiConfig) -> Option<Keytab> {
+    let path = match Keytab::resolve_path(config.keytab.as_deref()) {
+        Some(p) => p,
+        None => {
+            report.warn("keytab", "The keytab is not a file keytab, skipping keytab checks");
+            return None;
+        }
+    };
+    let display = path.display();
+
+    match std::fs::metadata(&path) {
+        Ok(meta) => {
+            let mode = meta.permissions().mode() & 0o777;
+            let check = "keytab permissions";
+            if mode & 0o007 != 0 {
+                report.fail(check, format!("{} is world accessible ({:o})", display, mode));
+            } else if mode & 0o070 != 0 {
+                report.warn(check, format!("{} is group accessible ({:o})", display, mode));
+            } else {
+                report.pass(check, format!("{} ({:o})", display, mode));
+            }
+        }
+        Err(e) => {
+            report.fail("keytab", format!("{}: {}", display, e));
+            return None;
+        }
+    }
+
+    let keytab = match Keytab::read(&path) {
+        Ok(k) => k,
+        Err(e) => {
+            report.fail("keytab", format!("{}: {}", display, e));
+            return None;
+        }
+    };
+    if keytab.entries.is_empty() {
+        report.fail("keytab", format!("{} has no keys", display));
+        return Some(keytab);
+    }
+    for principal in keytab.principals() {
+        let keys = keytab.current_keys(&principal);
+        let enctypes: Vec<String> = keys.iter().map(|k| k.enctype_name()).collect();
+        let kvno = keys.first().map_or(0, |k| k.kvno);
+        let detail = format!("{} kvno {}: {}", principal, kvno, enctypes.join(", "));
+        if keys.iter().all(|k| k.is_weak()) {
+            report.warn("keytab", format!("{} (only weak enctypes)", detail));
+        } else {
+            report.pass("keytab", detail);
+        }
+    }
+    Some(keytab)
+}
+
+fn check_service_name(report: &mut Report, config: &GssapiConfig) {
+    let principal = match &config.principal {
+        Some(p) => p,
+        None => {
+            report.pass("service name", "unset, any principal in the keytab is accepted");
+            return;
+        }
+    };
+    let canonical = Name::new(principal.as_bytes(), Some(config.name_type.oid()))
+        .and_then(|n| n.canonicalize(Some(&GSS_MECH_KRB5)));
+    match canonical {
+        Ok(n) => report.pass("service name", format!("{} canonicalizes to {}", principal, n)),
+        Err(e) => report.fail("service name", format!("{}: {}", principal, e)),
+    }
+}
+
+/// Builds the fairing from the configuration and acquires acceptor credentials like on ignite.
+fn check_credentials(report: &mut Report, config: &GssapiConfig) {
+    let fairing = match GssapiFairing::from_config(config) {
+        Ok(f) => f,
+        Err(e) => {
+            report.fail("credentials", e.to_string());
+            return;
+        }
+    };
+    match fairing.validate() {
+        Ok(()) => report.pass("credentials", "acceptor credentials acquired"),
+        Err(e) => report.fail("credentials", e.to_string()),
+    }
+}
+
+/// Extracts the token of a Negotiate header, given as a whole header line, just its value or
+/// only the base64 token.
+fn negotiate_token(header: &str) -> Result<Vec<u8>, String> {
+    let value = header
+        .trim()
+        .split_once(':')
+        .map_or(header.trim(), |(_, v)| v.trim());
+    let encoded = match value.split_once(' ') {
+        Some((scheme, token)) if scheme.eq_ignore_ascii_case("Negotiate") => token.trim(),
+        Some((scheme, _)) => return Err(format!("{} is not a Negotiate header", scheme)),
+        None => value,
+    };
+    BASE64_STANDARD
+        .decode(encoded)
+        .map_err(|e| format!("invalid base64: {}", e))
+}
+
+/// Decodes a pasted Negotiate header and compares the ticket in it with the keytab.
+fn check_token(report: &mut Report, header: &str, keytab: Option<&Keytab>) {
+    let header = if header == "-" {
+        let mut buf = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut buf) {
+            report.fail("token", format!("failed to read stdin: {}", e));
+            return;
+        }
+        buf
+    } else {
+        header.to_string()
+    };
+    let token = match negotiate_token(&header) {
+        Ok(t) => t,
+        Err(e) => {
+            report.fail("token", e);
+            return;
+        }
+    };
+
+    match NegToken::parse(&token) {
+        Ok(neg) if TokenKind::of(&token) == TokenKind::Ntlm => {
+            report.fail("token", format!("{}, Kerberos was not attempted", neg))
+        }
+        Ok(neg) => report.pass("token", neg.to_string()),
+        Err(SpnegoError::Malformed) => report.fail("token", "malformed SPNEGO token"),
+        Err(SpnegoError::NotSpnego) => match TokenKind::of(&token) {
+            TokenKind::Ntlm => report.fail("token", "NTLM token, Kerberos was not attempted"),
+            TokenKind::Unknown => report.fail("token", "unrecognized token"),
+            kind => report.pass("token", format!("{} token", kind)),
+        },
+    }
+
+    let ap_req = match ApReq::from_token(&token) {
+        Some(a) => a,
+        None => {
+            report.warn("ticket", "the token carries no Kerberos ticket");
+            return;
+        }
+    };
+    report.pass("ticket", ap_req.to_string());
+    match keytab.map(|k| ap_req.mismatch(k)) {
+        Some(Some(mismatch)) => report.fail("ticket", mismatch),
+        Some(None) => report.pass("ticket", "matches a key in the keytab"),
+        None => report.warn("ticket", "keytab unavailable, not compared"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KRB5_CONF: &str = "\
+[libdefaults]
+    dns_lookup_realm = false
+    default_realm = CORP.EXAMPLE
+
+[realms]
+    CORP.EXAMPLE = {
+        kdc = dc1.corp.example
+    }
+";
+
+    #[test]
+    fn extracts_negotiate_tokens() {
+        let token = vec![0xa1, 0x03, 0x30, 0x01, 0x00];
+        for header in [
+            "Authorization: Negotiate oQMwAQA=",
+            "WWW-Authenticate: negotiate   oQMwAQA=\n",
+            "Negotiate oQMwAQA=",
+            " oQMwAQA= ",
+        ] {
+            assert_eq!(negotiate_token(header), Ok(token.clone()), "{}", header);
+        }
+    }
+
+    #[test]
+    fn rejects_other_headers() {
+        assert_eq!(
+            negotiate_token("Authorization: Basic YWxpY2U6c2VjcmV0"),
+            Err("Basic is not a Negotiate header".to_string())
+        );
+        assert!(negotiate_token("Negotiate not base64!").is_err_and(|e| e.starts_with("invalid")));
+    }
+
+    #[test]
+    fn finds_default_realm() {
+        assert_eq!(default_realm(KRB5_CONF), Some("CORP.EXAMPLE"));
+        assert_eq!(default_realm("[libdefaults]\ndefault_realm=CORP\n"), Some("CORP"));
+        assert_eq!(default_realm("[libdefaults]\ndefault_realms = CORP\n"), None);
+        assert_eq!(default_realm("[appdefaults]\ndefault_realm = CORP\n"), None);
+        assert_eq!(default_realm(""), None);
+    }
+
+    #[test]
+    fn counts_failures_and_warnings() {
+        let mut report = Report::default();
+        report.pass("check", "passed");
+        report.warn("check", "warned");
+        assert!(report.succeeded());
+        report.fail("check", "failed");
+        assert!(!report.succeeded());
+        assert_eq!((report.failures, report.warnings), (1, 1));
+    }
+
+    #[test]
+    fn skips_unreadable_krb5_conf() {
+        let name = format!("rocket-gssapi-doctor-{}.conf", std::process::id());
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, KRB5_CONF).unwrap();
+        let missing = "/nonexistent/krb5.conf";
+
+        let mut report = Report::default();
+        check_krb5_conf(&mut report, &format!("{}:{}", missing, path.display()));
+        assert_eq!((report.failures, report.warnings), (0, 1));
+
+        let mut report = Report::default();
+        check_krb5_conf(&mut report, missing);
+        assert_eq!((report.failures, report.warnings), (1, 1));
+
+        std::fs::write(&path, "[libdefaults]\n").unwrap();
+        let mut report = Report::default();
+        check_krb5_conf(&mut report, &path.to_string_lossy());
+        assert_eq!((report.failures, report.warnings), (0, 1));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,336 @@
+use crate::cred::CredCache;
+use crate::error::ConfigError;
+use crate::fairing::{GssapiFairing, IdentifierFunction, ResponseBehavior};
+use crate::group::GroupResolver;
+use crate::policy::PrincipalPolicy;
+use crate::realm::RealmPolicy;
+use crate::store::{ContextLimits, ContextStore, MemoryStore};
+use crate::token::TokenPolicy;
+use libgssapi::context::CtxFlags;
+use libgssapi::credential::CredUsage;
+use libgssapi::name::Name;
+use libgssapi::oid::OidSet;
+use rocket::Request;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Builds a [`GssapiFairing`], see [`GssapiFairing::builder()`].
+///
+/// ```rust,no_run
+/// use rocket_gssapi::GssapiFairing;
+/// use rocket_gssapi::name::Name;
+/// use rocket_gssapi::oid::GSS_NT_KRB5_PRINCIPAL;
+/// use std::time::Duration;
+///
+/// let name = Name::new(b"HTTP/example@example.com", Some(&GSS_NT_KRB5_PRINCIPAL)).unwrap();
+/// let fairing = GssapiFairing::builder()
+///     .principal(name)
+///     .idle_timeout(Duration::from_secs(10))
+///     .build()
+///     .expect("Invalid GSSAPI configuration");
+/// ```
+pub struct GssapiFairingBuilder {
+    name: Option<Name>,
+    desired_mechs: Option<OidSet>,
+    usage: CredUsage,
+    keytab: Option<PathBuf>,
+    ccache: Option<String>,
+    identifier: Box<IdentifierFunction>,
+    store: Arc<dyn ContextStore>,
+    limits: ContextLimits,
+    sweep_interval: Duration,
+    cred_refresh_interval: Duration,
+    keytab_poll_interval: Duration,
+    policy_poll_interval: Duration,
+    realms: RealmPolicy,
+    policy: Option<PrincipalPolicy>,
+    response: ResponseBehavior,
+    groups: Option<Arc<dyn GroupResolver>>,
+    required_flags: CtxFlags,
+    forbidden_flags: CtxFlags,
+    tokens: TokenPolicy,
+}
+impl Default for GssapiFairingBuilder {
+    fn default() -> Self {
+        GssapiFairingBuilder {
+            name: None,
+            desired_mechs: None,
+            usage: CredUsage::Accept,
+            keytab: None,
+            ccache: None,
+            identifier: Box::new(|r| r.client_ip().map(|ip| ip.to_string())),
+            store: Arc::new(MemoryStore::new()),
+            limits: ContextLimits::default(),
+            sweep_interval: Duration::from_secs(10),
+            cred_refresh_interval: Duration::from_secs(3600),
+            keytab_poll_interval: Duration::from_secs(30),
+            policy_poll_interval: Duration::from_secs(30),
+            realms: RealmPolicy::default(),
+            policy: None,
+            response: ResponseBehavior::default(),
+            groups: None,
+            required_flags: CtxFlags::empty(),
+            forbidden_flags: CtxFlags::empty(),
+            tokens: TokenPolicy::default(),
+        }
+    }
+}
+impl GssapiFairingBuilder {
+    /// The service principal to accept contexts for. Uses the system default if unset.
+    pub fn principal(mut self, name: Name) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// The accepted mechanisms. Uses the system default if unset.
+    pub fn mechanisms(mut self, desired_mechs: OidSet) -> Self {
+        self.desired_mechs = Some(desired_mechs);
+        self
+    }
+
+    /// The usage of the acquired credentials. Must be `Accept` or `Both`, defaults to `Accept`.
+    pub fn usage(mut self, usage: CredUsage) -> Self {
+        self.usage = usage;
+        self
+    }
+
+    /// The keytab to acquire the acceptor credentials from, instead of the process-wide
+    /// `KRB5_KTNAME`. Lets several fairings in one process use their own keytabs.
+    pub fn keytab(mut self, keytab: impl Into<PathBuf>) -> Self {
+        self.keytab = Some(keytab.into());
+        self
+    }
+
+    /// The client credential cache to use alongside the keytab, e.g. for S4U, instead of the
+    /// process-wide `KRB5CCNAME`. Takes a ccache name such as `FILE:/run/http.ccache`.
+    pub fn ccache(mut self, ccache: impl Into<String>) -> Self {
+        self.ccache = Some(ccache.into());
+        self
+    }
+
+    /// How clients are identified in order to work their SecurityContexts to completion.
+    /// Defaults to the `Request::client_ip()` result.
+    pub fn identifier<F>(mut self, identifier: F) -> Self
+    where
+        F: Fn(&mut Request) -> Option<String> + Send + Sync + 'static,
+    {
+        self.identifier = Box::new(identifier);
+        self
+    }
+
+    /// Where half-finished contexts are kept between requests. Defaults to a [`MemoryStore`].
+    pub fn store<S: ContextStore + 'static>(mut self, store: S) -> Self {
+        self.store = Arc::new(store);
+        self
+    }
+
+    /// How long a half-finished context waits for the next token. Defaults to 30 seconds.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.limits.idle_timeout = timeout;
+        self
+    }
+
+    /// The maximum time a negotiation may take in total. Defaults to 2 minutes.
+    pub fn negotiation_timeout(mut self, timeout: Duration) -> Self {
+        self.limits.negotiation_timeout = timeout;
+        self
+    }
+
+    /// How often stale contexts are pruned. Defaults to 10 seconds.
+    pub fn sweep_interval(mut self, interval: Duration) -> Self {
+        self.sweep_interval = interval;
+        self
+    }
+
+    /// The maximum number of half-finished contexts kept at once. Defaults to 1024.
+    pub fn max_contexts(mut self, max: usize) -> Self {
+        self.limits.max_contexts = max;
+        self
+    }
+
+    /// The maximum number of concurrent negotiations from a single client IP address.
+    /// Defaults to 4.
+    pub fn max_contexts_per_client(mut self, max: usize) -> Self {
+        self.limits.max_contexts_per_client = max;
+        self
+    }
+
+    /// How often the acceptor credentials are re-acquired. Defaults to 1 hour.
+    pub fn cred_refresh_interval(mut self, interval: Duration) -> Self {
+        self.cred_refresh_interval = interval;
+        self
+    }
+
+    /// How often the keytab is checked for changes, reloading the credentials when it is
+    /// replaced. Set to zero to disable. Defaults to 30 seconds.
+    ///
+    /// MIT Kerberos reads the keytab for every new context, so replace it by renaming a
+    /// complete file over it rather than rewriting it in place.
+    pub fn keytab_poll_interval(mut self, interval: Duration) -> Self {
+        self.keytab_poll_interval = interval;
+        self
+    }
+
+    /// How often a policy loaded from a file is checked for changes, reloading it when it is
+    /// replaced. Set to zero to disable. Defaults to 30 seconds.
+    pub fn policy_poll_interval(mut self, interval: Duration) -> Self {
+        self.policy_poll_interval = interval;
+        self
+    }
+
+    /// The realms clients may authenticate from. Defaults to all realms trusted by the KDC.
+    pub fn accepted_realms(mut self, realms: RealmPolicy) -> Self {
+        self.realms = realms;
+        self
+    }
+
+    /// Allow and deny rules for authenticated principals. A policy loaded from a file is
+    /// reloaded whenever the file changes.
+    pub fn policy(mut self, policy: PrincipalPolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// How groups are looked up for clients whose ticket has no PAC. Without a resolver, only
+    /// groups from the PAC are known.
+    pub fn group_resolver<R: GroupResolver + 'static>(mut self, resolver: R) -> Self {
+        self.groups = Some(Arc::new(resolver));
+        self
+    }
+
+    /// The flags a context must have been established with, e.g. `GSS_C_MUTUAL_FLAG`.
+    pub fn required_flags(mut self, flags: CtxFlags) -> Self {
+        self.required_flags = flags;
+        self
+    }
+
+    /// The flags a context must not have been established with, e.g. `GSS_C_ANON_FLAG`.
+    pub fn forbidden_flags(mut self, flags: CtxFlags) -> Self {
+        self.forbidden_flags = flags;
+        self
+    }
+
+    /// Which kinds of tokens are passed to GSSAPI. Defaults to rejecting NTLM.
+    pub fn token_policy(mut self, policy: TokenPolicy) -> Self {
+        self.tokens = policy;
+        self
+    }
+
+    /// Which responses carry a `WWW-Authenticate: Negotiate` header.
+    pub fn response(mut self, response: ResponseBehavior) -> Self {
+        self.response = response;
+        self
+    }
+
+    /// Validates the options and builds the fairing.
+    pub fn build(self) -> Result<GssapiFairing, ConfigError> {
+        if matches!(self.usage, CredUsage::Initiate) {
+            return Err(ConfigError::InvalidUsage);
+        }
+        if self.sweep_interval.is_zero() {
+            return Err(ConfigError::InvalidOption("sweep interval must be non-zero"));
+        }
+        if self.limits.max_contexts == 0 || self.limits.max_contexts_per_client == 0 {
+            return Err(ConfigError::InvalidOption("context limits must be non-zero"));
+        }
+        if self.limits.idle_timeout > self.limits.negotiation_timeout {
+            return Err(ConfigError::InvalidOption(
+                "idle timeout must not exceed the negotiation timeout",
+            ));
+        }
+
+        if self.required_flags.intersects(self.forbidden_flags) {
+            return Err(ConfigError::InvalidOption(
+                "context flags can't be both required and forbidden",
+            ));
+        }
+
+        let mut creds = CredCache::new(self.name, self.desired_mechs, self.usage);
+        creds.set_refresh_interval(self.cred_refresh_interval);
+        creds.set_store(self.keytab, self.ccache)?;
+        Ok(GssapiFairing {
+            creds: Arc::new(creds),
+            identifier: self.identifier,
+            contexts: self.store,
+            limits: self.limits,
+            sweep_interval: self.sweep_interval,
+            keytab_poll_interval: self.keytab_poll_interval,
+            policy_poll_interval: self.policy_poll_interval,
+            realms: self.realms,
+            policy: self.policy.map(Arc::new),
+            response: self.response,
+            groups: self.groups,
+            required_flags: self.required_flags,
+            forbidden_flags: self.forbidden_flags,
+            tokens: self.tokens,
+            mismatch_warned: Mutex::new(None),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid(builder: GssapiFairingBuilder) -> Option<&'static str> {
+        match builder.build() {
+            Err(ConfigError::InvalidOption(reason)) => Some(reason),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn builds_with_defaults() {
+        assert!(GssapiFairingBuilder::default().build().is_ok());
+    }
+
+    #[test]
+    fn rejects_initiate_usage() {
+        let builder = GssapiFairingBuilder::default().usage(CredUsage::Initiate);
+        assert!(matches!(builder.build(), Err(ConfigError::InvalidUsage)));
+        let builder = GssapiFairingBuilder::default().usage(CredUsage::Both);
+        assert!(builder.build().is_ok());
+    }
+
+    #[test]
+    fn rejects_zero_intervals_and_limits() {
+        let builder = GssapiFairingBuilder::default();
+        assert!(invalid(builder.sweep_interval(Duration::ZERO)).is_some());
+        assert!(invalid(GssapiFairingBuilder::default().max_contexts(0)).is_some());
+        assert!(invalid(GssapiFairingBuilder::default().max_contexts_per_client(0)).is_some());
+        // Disables polling rather than being invalid
+        let builder = GssapiFairingBuilder::default().keytab_poll_interval(Duration::ZERO);
+        assert!(builder.build().is_ok());
+    }
+
+    #[test]
+    fn rejects_idle_timeout_above_negotiation_timeout() {
+        let builder = GssapiFairingBuilder::default()
+            .idle_timeout(Duration::from_secs(60))
+            .negotiation_timeout(Duration::from_secs(30));
+        assert!(invalid(builder).is_some());
+        let builder = GssapiFairingBuilder::default()
+            .idle_timeout(Duration::from_secs(30))
+            .negotiation_timeout(Duration::from_secs(30));
+        assert!(builder.build().is_ok());
+    }
+
+    #[test]
+    fn rejects_flags_both_required_and_forbidden() {
+        let builder = GssapiFairingBuilder::default()
+            .required_flags(CtxFlags::GSS_C_MUTUAL_FLAG | CtxFlags::GSS_C_INTEG_FLAG)
+            .forbidden_flags(CtxFlags::GSS_C_INTEG_FLAG);
+        assert!(invalid(builder).is_some());
+        let builder = GssapiFairingBuilder::default()
+            .required_flags(CtxFlags::GSS_C_MUTUAL_FLAG)
+            .forbidden_flags(CtxFlags::GSS_C_ANON_FLAG);
+        assert!(builder.build().is_ok());
+    }
+
+    #[test]
+    fn rejects_nul_in_credential_store() {
+        assert!(invalid(GssapiFairingBuilder::default().keytab("/etc/http\0.keytab")).is_some());
+        assert!(invalid(GssapiFairingBuilder::default().ccache("FILE:/tmp/\0cc")).is_some());
+    }
+}
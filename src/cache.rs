@@ -0,0 +1,193 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError, RwLock, TryLockError};
+use std::time::{Duration, Instant};
+
+/// A value that is refreshed once it is older than an interval, such as the acceptor
+/// credential of a fairing.
+///
+/// Only one caller refreshes at a time. Callers finding a refresh in progress keep using the
+/// old value, so an expired value doesn't send every concurrent request to the keytab.
+pub(crate) struct TimedCache<T> {
+    value: RwLock<Option<(T, Instant)>>,
+    interval_ms: AtomicU64,
+    refreshing: Mutex<()>,
+}
+impl<T: Clone> TimedCache<T> {
+    pub fn new(interval: Duration) -> TimedCache<T> {
+        let cache = TimedCache {
+            value: RwLock::new(None),
+            interval_ms: AtomicU64::new(0),
+            refreshing: Mutex::new(()),
+        };
+        cache.set_interval(interval);
+        cache
+    }
+
+    /// Sets how old the value may get before it is refreshed. Takes effect for the next
+    /// lookup, also when the cache is shared.
+    pub fn set_interval(&self, interval: Duration) {
+        let ms = u64::try_from(interval.as_millis()).unwrap_or(u64::MAX);
+        self.interval_ms.store(ms, Ordering::Relaxed);
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms.load(Ordering::Relaxed))
+    }
+
+    /// The value if it is younger than the interval, else the stale value if there is one.
+    fn current(&self) -> Result<T, Option<T>> {
+        let value = self.value.read().unwrap_or_else(PoisonError::into_inner);
+        match value.as_ref() {
+            Some((v, at)) if at.elapsed() < self.interval() => Ok(v.clone()),
+            v => Err(v.map(|(v, _)| v.clone())),
+        }
+    }
+
+    /// Returns the cached value, calling `refresh` if there is none or if it is due. The
+    /// argument of `refresh` tells whether a stale value is being replaced, which is kept in
+    /// use should the refresh fail.
+    pub fn get<E>(&self, refresh: impl FnOnce(bool) -> Result<T, E>) -> Result<T, E> {
+        let stale = match self.current() {
+            Ok(v) => return Ok(v),
+            Err(stale) => stale,
+        };
+
+        let _refreshing = match (self.refreshing.try_lock(), stale) {
+            (Ok(guard), _) => guard,
+            (Err(TryLockError::Poisoned(e)), _) => e.into_inner(),
+            (Err(TryLockError::WouldBlock), Some(stale)) => return Ok(stale),
+            // Nothing to serve meanwhile, so wait for the refresh in progress
+            (Err(TryLockError::WouldBlock), None) => self
+                .refreshing
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        };
+        // Another caller may have refreshed it while this one was waiting
+        let stale = match self.current() {
+            Ok(v) => return Ok(v),
+            Err(stale) => stale,
+        };
+
+        match (refresh(stale.is_some()), stale) {
+            (Ok(v), _) => {
+                self.replace(v.clone());
+                Ok(v)
+            }
+            (Err(_), Some(stale)) => Ok(stale),
+            (Err(e), None) => Err(e),
+        }
+    }
+
+    /// Replaces the value, restarting its interval.
+    pub fn replace(&self, value: T) {
+        let mut cached = self.value.write().unwrap_or_else(PoisonError::into_inner);
+        *cached = Some((value, Instant::now()));
+    }
+
+    /// Drops the value, so it is refreshed on the next lookup.
+    pub fn clear(&self) {
+        let mut cached = self.value.write().unwrap_or_else(PoisonError::into_inner);
+        *cached = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+
+    fn never(_: bool) -> Result<u32, ()> {
+        panic!("refreshed a fresh value")
+    }
+
+    #[test]
+    fn acquires_once_and_serves_until_due() {
+        let cache = TimedCache::new(Duration::from_secs(3600));
+        assert_eq!(
+            cache.get(|stale| if stale { Err(()) } else { Ok(1) }),
+            Ok(1)
+        );
+        assert_eq!(cache.get(never), Ok(1));
+    }
+
+    #[test]
+    fn refreshes_when_due() {
+        let cache = TimedCache::new(Duration::ZERO);
+        cache.replace(1);
+        assert_eq!(
+            cache.get(|stale| if stale { Ok::<_, ()>(2) } else { Err(()) }),
+            Ok(2)
+        );
+    }
+
+    #[test]
+    fn keeps_stale_value_when_refresh_fails() {
+        let cache = TimedCache::new(Duration::ZERO);
+        assert_eq!(cache.get(|_| Err("no keytab")), Err("no keytab"));
+        cache.replace(1);
+        assert_eq!(cache.get(|_| Err("no keytab")), Ok(1));
+    }
+
+    #[test]
+    fn clear_forces_a_refresh() {
+        let cache = TimedCache::new(Duration::from_secs(3600));
+        cache.replace(1);
+        cache.clear();
+        assert_eq!(
+            cache.get(|stale| if stale { Err(()) } else { Ok(2) }),
+            Ok(2)
+        );
+    }
+
+    #[test]
+    fn interval_is_changed_through_a_shared_cache() {
+        let cache = Arc::new(TimedCache::new(Duration::from_secs(3600)));
+        let shared = Arc::clone(&cache);
+        cache.replace(1);
+        assert_eq!(shared.get(never), Ok(1));
+
+        cache.set_interval(Duration::ZERO);
+        assert_eq!(shared.interval(), Duration::ZERO);
+        assert_eq!(shared.get(|_| Ok::<_, ()>(2)), Ok(2));
+    }
+
+    #[test]
+    fn serves_stale_value_while_refreshing() {
+        let cache = TimedCache::new(Duration::ZERO);
+        cache.replace(1);
+        let _refreshing = cache.refreshing.lock().unwrap();
+        assert_eq!(cache.get(never), Ok(1));
+    }
+
+    #[test]
+    fn refreshes_once_for_concurrent_callers() {
+        let threads = 8;
+        let cache = Arc::new(TimedCache::new(Duration::from_millis(50)));
+        cache.replace(0);
+        thread::sleep(Duration::from_millis(60));
+
+        let refreshes = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(threads));
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                let (cache, refreshes) = (Arc::clone(&cache), Arc::clone(&refreshes));
+                let barrier = Arc::clone(&barrier);
+                thread::spawn(move || {
+                    barrier.wait();
+                    cache.get(|_| {
+                        refreshes.fetch_add(1, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(100));
+                        Ok::<_, ()>(1)
+                    })
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert!(matches!(handle.join().unwrap(), Ok(0) | Ok(1)));
+        }
+        assert_eq!(refreshes.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.get(never), Ok(1));
+    }
+}
@@ -0,0 +1,363 @@
+use crate::token::TokenAction;
+use libgssapi::context::CtxFlags;
+use libgssapi::oid::{
+    Oid, GSS_MECH_IAKERB, GSS_MECH_KRB5, GSS_MECH_SPNEGO, GSS_NT_HOSTBASED_SERVICE,
+    GSS_NT_KRB5_PRINCIPAL, GSS_NT_USER_NAME,
+};
+use rocket::serde::Deserialize;
+use rocket::Request;
+use std::fmt;
+use std::path::PathBuf;
+
+/// The `gssapi` section of the Rocket configuration, read by [`GssapiFairing::fairing()`].
+/// A missing section is the same as an empty one, using system defaults for everything.
+///
+/// ```toml
+/// [default.gssapi]
+/// principal = "HTTP/www.example.com@EXAMPLE.COM"
+/// keytab = "/etc/http.keytab"
+/// mechanisms = ["kerberos"]
+///
+/// [staging.gssapi]
+/// principal = "HTTP/staging.example.com@EXAMPLE.COM"
+/// ```
+///
+/// [`GssapiFairing::fairing()`]: crate::GssapiFairing::fairing
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct GssapiConfig {
+    /// The service principal to accept contexts for. Uses the system default if unset.
+    #[serde(default)]
+    pub principal: Option<String>,
+    /// How `principal` is to be interpreted.
+    #[serde(default)]
+    pub name_type: NameType,
+    /// The keytab holding the key of `principal`. Uses `KRB5_KTNAME` if unset.
+    #[serde(default)]
+    pub keytab: Option<PathBuf>,
+    /// The client credential cache, e.g. for S4U. Uses `KRB5CCNAME` if unset.
+    #[serde(default)]
+    pub ccache: Option<String>,
+    /// The accepted mechanisms. Uses the system default if empty.
+    #[serde(default)]
+    pub mechanisms: Vec<Mechanism>,
+    /// Seconds a half-finished context is kept waiting for the next token from the client.
+    /// Negotiations are allowed to take at least this long in total.
+    #[serde(default = "default_context_timeout")]
+    pub context_timeout: u64,
+    /// Seconds between checks of the keytab for changes, zero to disable.
+    #[serde(default = "default_poll_interval")]
+    pub keytab_poll_interval: u64,
+    /// Seconds between checks of the policy file for changes, zero to disable.
+    #[serde(default = "default_poll_interval")]
+    pub policy_poll_interval: u64,
+    /// Realms clients may authenticate from, e.g. `["CORP.EXAMPLE", "*.CORP.EXAMPLE"]`.
+    /// Accepts all realms if empty.
+    #[serde(default)]
+    pub realms: Vec<String>,
+    /// A TOML file with allow and deny rules for principals, see [`PolicyRules`].
+    ///
+    /// [`PolicyRules`]: crate::PolicyRules
+    #[serde(default)]
+    pub policy: Option<PathBuf>,
+    /// A TOML file listing the members of groups, for clients whose ticket has no PAC. See
+    /// [`FileGroupResolver`].
+    ///
+    /// [`FileGroupResolver`]: crate::FileGroupResolver
+    #[serde(default)]
+    pub groups: Option<PathBuf>,
+    /// Services a context must provide, e.g. `["mutual", "integrity"]`.
+    #[serde(default)]
+    pub required_flags: Vec<ContextFlag>,
+    /// Services a context must not use, e.g. `["anonymous"]`.
+    #[serde(default)]
+    pub forbidden_flags: Vec<ContextFlag>,
+    /// Whether NTLM tokens are rejected or passed to GSSAPI. Defaults to rejecting them.
+    #[serde(default = "default_ntlm")]
+    pub ntlm: TokenAction,
+    /// Whether Kerberos tokens not wrapped in SPNEGO are rejected or passed to GSSAPI.
+    /// Defaults to passing them.
+    #[serde(default = "default_raw_kerberos")]
+    pub raw_kerberos: TokenAction,
+    /// How clients are identified across the legs of a negotiation.
+    #[serde(default)]
+    pub identifier: Identifier,
+}
+
+impl Default for GssapiConfig {
+    fn default() -> Self {
+        GssapiConfig {
+            principal: None,
+            name_type: NameType::default(),
+            keytab: None,
+            ccache: None,
+            mechanisms: Vec::new(),
+            context_timeout: default_context_timeout(),
+            keytab_poll_interval: default_poll_interval(),
+            policy_poll_interval: default_poll_interval(),
+            realms: Vec::new(),
+            policy: None,
+            groups: None,
+            required_flags: Vec::new(),
+            forbidden_flags: Vec::new(),
+            ntlm: default_ntlm(),
+            raw_kerberos: default_raw_kerberos(),
+            identifier: Identifier::default(),
+        }
+    }
+}
+
+fn default_context_timeout() -> u64 {
+    30
+}
+
+fn default_poll_interval() -> u64 {
+    30
+}
+
+fn default_ntlm() -> TokenAction {
+    TokenAction::Reject
+}
+
+fn default_raw_kerberos() -> TokenAction {
+    TokenAction::PassThrough
+}
+
+/// The name types a service principal may be given in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum NameType {
+    /// A Kerberos principal such as `HTTP/www.example.com@EXAMPLE.COM`.
+    #[default]
+    KerberosPrincipal,
+    /// A host-based service such as `HTTP@www.example.com`.
+    HostbasedService,
+    /// A user name such as `alice`.
+    UserName,
+}
+impl NameType {
+    pub fn oid(&self) -> &'static Oid {
+        match self {
+            NameType::KerberosPrincipal => &GSS_NT_KRB5_PRINCIPAL,
+            NameType::HostbasedService => &GSS_NT_HOSTBASED_SERVICE,
+            NameType::UserName => &GSS_NT_USER_NAME,
+        }
+    }
+}
+
+/// The mechanisms that may be accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum Mechanism {
+    Kerberos,
+    Spnego,
+    Iakerb,
+}
+impl Mechanism {
+    pub fn oid(&self) -> &'static Oid {
+        match self {
+            Mechanism::Kerberos => &GSS_MECH_KRB5,
+            Mechanism::Spnego => &GSS_MECH_SPNEGO,
+            Mechanism::Iakerb => &GSS_MECH_IAKERB,
+        }
+    }
+}
+
+/// The context flags that may be required or forbidden.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum ContextFlag {
+    Mutual,
+    Delegation,
+    Integrity,
+    Confidentiality,
+    Anonymous,
+    Replay,
+    Sequence,
+}
+impl ContextFlag {
+    const ALL: [ContextFlag; 7] = [
+        ContextFlag::Mutual,
+        ContextFlag::Delegation,
+        ContextFlag::Integrity,
+        ContextFlag::Confidentiality,
+        ContextFlag::Anonymous,
+        ContextFlag::Replay,
+        ContextFlag::Sequence,
+    ];
+
+    pub fn flag(&self) -> CtxFlags {
+        match self {
+            ContextFlag::Mutual => CtxFlags::GSS_C_MUTUAL_FLAG,
+            ContextFlag::Delegation => CtxFlags::GSS_C_DELEG_FLAG,
+            ContextFlag::Integrity => CtxFlags::GSS_C_INTEG_FLAG,
+            ContextFlag::Confidentiality => CtxFlags::GSS_C_CONF_FLAG,
+            ContextFlag::Anonymous => CtxFlags::GSS_C_ANON_FLAG,
+            ContextFlag::Replay => CtxFlags::GSS_C_REPLAY_FLAG,
+            ContextFlag::Sequence => CtxFlags::GSS_C_SEQUENCE_FLAG,
+        }
+    }
+
+    /// Lists the named flags set in `flags`, e.g. `mutual, integrity`.
+    pub(crate) fn describe(flags: CtxFlags) -> String {
+        ContextFlag::ALL
+            .iter()
+            .filter(|f| flags.contains(f.flag()))
+            .map(|f| f.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+impl fmt::Display for ContextFlag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ContextFlag::Mutual => "mutual",
+            ContextFlag::Delegation => "delegation",
+            ContextFlag::Integrity => "integrity",
+            ContextFlag::Confidentiality => "confidentiality",
+            ContextFlag::Anonymous => "anonymous",
+            ContextFlag::Replay => "replay",
+            ContextFlag::Sequence => "sequence",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Strategies for identifying clients in order to work their contexts to completion.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum Identifier {
+    /// The `Request::client_ip()` of the client.
+    #[default]
+    ClientIp,
+    /// The remote address and port of the connection.
+    Remote,
+    /// The value of the given header together with the client IP, e.g. a session id set by a
+    /// proxy. The value must be issued by the server and unguessable, as a client sending the
+    /// value of another behind the same IP takes over its negotiation.
+    Header(String),
+}
+impl Identifier {
+    pub fn identify(&self, req: &Request<'_>) -> Option<String> {
+        match self {
+            Identifier::ClientIp => req.client_ip().map(|ip| ip.to_string()),
+            Identifier::Remote => req.remote().map(|r| r.to_string()),
+            Identifier::Header(h) => {
+                // Scoped to the IP, so a value guessed from elsewhere doesn't hijack a context
+                let value = req.headers().get_one(h)?;
+                let ip = req.client_ip().map_or(String::new(), |ip| ip.to_string());
+                Some(format!("{} {}", ip, value))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::figment::providers::{Format, Toml};
+    use rocket::figment::Figment;
+    use rocket::http::Header;
+    use rocket::local::blocking::Client;
+    use std::net::SocketAddr;
+
+    fn extract(toml: &str) -> Option<GssapiConfig> {
+        Figment::from(Toml::string(toml))
+            .extract_inner("gssapi")
+            .ok()
+    }
+
+    #[test]
+    fn extracts_defaults_from_empty_section() {
+        let config = extract("[gssapi]").unwrap();
+        assert_eq!(config.principal, None);
+        assert_eq!(config.name_type, NameType::KerberosPrincipal);
+        assert!(config.mechanisms.is_empty());
+        assert_eq!(config.context_timeout, 30);
+        assert_eq!(config.keytab_poll_interval, 30);
+        assert_eq!(config.ntlm, TokenAction::Reject);
+        assert_eq!(config.raw_kerberos, TokenAction::PassThrough);
+        assert_eq!(config.identifier, Identifier::ClientIp);
+    }
+
+    #[test]
+    fn extracts_every_key() {
+        let config = extract(
+            r#"
+            [gssapi]
+            principal = "HTTP@www.example.com"
+            name_type = "hostbased_service"
+            keytab = "/etc/http.keytab"
+            ccache = "FILE:/run/http.ccache"
+            mechanisms = ["spnego", "kerberos", "iakerb"]
+            context_timeout = 10
+            keytab_poll_interval = 0
+            realms = ["CORP.EXAMPLE", "*.CORP.EXAMPLE"]
+            required_flags = ["mutual", "integrity"]
+            forbidden_flags = ["anonymous"]
+            ntlm = "pass_through"
+            identifier = "remote"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.principal.as_deref(), Some("HTTP@www.example.com"));
+        assert_eq!(config.name_type, NameType::HostbasedService);
+        assert_eq!(config.name_type.oid(), &GSS_NT_HOSTBASED_SERVICE);
+        assert_eq!(config.keytab, Some(PathBuf::from("/etc/http.keytab")));
+        assert_eq!(config.ccache.as_deref(), Some("FILE:/run/http.ccache"));
+        assert_eq!(
+            config.mechanisms,
+            [Mechanism::Spnego, Mechanism::Kerberos, Mechanism::Iakerb]
+        );
+        assert_eq!(config.mechanisms[1].oid(), &GSS_MECH_KRB5);
+        assert_eq!(config.context_timeout, 10);
+        assert_eq!(config.keytab_poll_interval, 0);
+        assert_eq!(config.realms, ["CORP.EXAMPLE", "*.CORP.EXAMPLE"]);
+        assert_eq!(
+            config.required_flags,
+            [ContextFlag::Mutual, ContextFlag::Integrity]
+        );
+        assert_eq!(config.forbidden_flags, [ContextFlag::Anonymous]);
+        assert_eq!(config.ntlm, TokenAction::PassThrough);
+        assert_eq!(config.identifier, Identifier::Remote);
+    }
+
+    #[test]
+    fn extracts_header_identifier_table() {
+        let config = extract("[gssapi]\nidentifier = { header = \"X-Session\" }").unwrap();
+        assert_eq!(config.identifier, Identifier::Header("X-Session".into()));
+    }
+
+    #[test]
+    fn rejects_unknown_values() {
+        assert!(extract("[gssapi]\nname_type = \"enterprise\"").is_none());
+        assert!(extract("[gssapi]\nmechanisms = [\"ntlm\"]").is_none());
+        assert!(extract("[gssapi]\nrequired_flags = [\"fast\"]").is_none());
+        assert!(extract("[gssapi]\nidentifier = \"cookie\"").is_none());
+    }
+
+    #[test]
+    fn describes_flags() {
+        let flags = CtxFlags::GSS_C_MUTUAL_FLAG | CtxFlags::GSS_C_ANON_FLAG;
+        assert_eq!(ContextFlag::describe(flags), "mutual, anonymous");
+        assert_eq!(ContextFlag::describe(CtxFlags::empty()), "");
+    }
+
+    #[test]
+    fn header_identifier_is_scoped_to_the_client_ip() {
+        let client = Client::untracked(rocket::build()).unwrap();
+        let identifier = Identifier::Header("X-Session".into());
+        let request = |ip: [u8; 4]| {
+            client
+                .get("/")
+                .header(Header::new("X-Session", "abc"))
+                .remote(SocketAddr::from((ip, 8000)))
+        };
+
+        let one = identifier.identify(request([192, 0, 2, 1]).inner());
+        let other = identifier.identify(request([192, 0, 2, 2]).inner());
+        assert_eq!(one.as_deref(), Some("192.0.2.1 abc"));
+        assert_ne!(one, other);
+        assert_eq!(identifier.identify(client.get("/").inner()), None);
+    }
+}
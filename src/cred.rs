@@ -0,0 +1,433 @@
+use crate::cache::TimedCache;
+use crate::error::ConfigError;
+use crate::keytab::{Keytab, KeytabError};
+use crate::watch::watch_file;
+use crate::ffi::{
+    gss_acquire_cred_from, gss_key_value_element_desc, gss_key_value_set_desc, GSS_C_ACCEPT,
+    GSS_C_BOTH, GSS_C_INDEFINITE, GSS_C_INITIATE, GSS_S_COMPLETE,
+};
+use libgssapi::credential::{Cred, CredUsage};
+use libgssapi::error::{Error, MajorFlags};
+use libgssapi::name::Name;
+use libgssapi::oid::{Oid, OidSet, GSS_MECH_IAKERB, GSS_MECH_KRB5, GSS_MECH_SPNEGO};
+use rocket::{debug, info, warn, Shutdown};
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::{Arc, RwLock, Weak};
+use std::time::Duration;
+
+/// Holds the acceptor credential of the fairing, so the keytab isn't re-read for every new
+/// negotiation.
+///
+/// The credential is acquired on ignite, and re-acquired when it is older than the refresh
+/// interval or after it has been invalidated by a failing context. Only one request at a time
+/// re-acquires it, the others keep using the old credential meanwhile. The keys of the keytab are
+/// kept as well, to explain failing contexts without reading the keytab for each of them.
+///
+/// MIT Kerberos reads the keytab on every accept, so an old credential doesn't keep the old
+/// keys in use. Keeping it only preserves the principal and mechanisms it was checked for.
+pub(crate) struct CredCache {
+    name: Option<Name>,
+    desired_mechs: Option<OidSet>,
+    usage: CredUsage,
+    keytab: Option<PathBuf>,
+    store: Vec<(CString, CString)>,
+    cached: TimedCache<Cred>,
+    keys: RwLock<Option<Arc<Keytab>>>,
+}
+impl CredCache {
+    pub fn new(name: Option<Name>, desired_mechs: Option<OidSet>, usage: CredUsage) -> CredCache {
+        CredCache {
+            name,
+            desired_mechs,
+            usage,
+            keytab: None,
+            store: Vec::new(),
+            cached: TimedCache::new(Duration::from_secs(3600)),
+            keys: RwLock::new(None),
+        }
+    }
+
+    pub fn set_refresh_interval(&self, interval: Duration) {
+        self.cached.set_interval(interval);
+    }
+
+    /// Sets the credential store used when acquiring credentials, instead of the process-wide
+    /// `KRB5_KTNAME` and `KRB5CCNAME` defaults.
+    pub fn set_store(
+        &mut self,
+        keytab: Option<PathBuf>,
+        ccache: Option<String>,
+    ) -> Result<(), ConfigError> {
+        let nul = || ConfigError::InvalidOption("credential store paths must not contain NUL");
+        let mut store = Vec::new();
+        if let Some(keytab) = &keytab {
+            let path = CString::new(keytab.as_os_str().as_bytes()).map_err(|_| nul())?;
+            store.push((CString::from(c"keytab"), path));
+        }
+        if let Some(ccache) = ccache {
+            let ccache = CString::new(ccache).map_err(|_| nul())?;
+            store.push((CString::from(c"ccache"), ccache));
+        }
+        self.keytab = keytab;
+        self.store = store;
+        Ok(())
+    }
+
+    /// The configured service principal in its canonical Kerberos form, as found in the
+    /// keytab, e.g. `HTTP/www.example.com@EXAMPLE.COM`.
+    pub fn principal(&self) -> Option<String> {
+        let name = self.name.as_ref()?.canonicalize(Some(&GSS_MECH_KRB5)).ok()?;
+        Some(name.to_string())
+    }
+
+    /// The keytab explicitly configured for this fairing, if any.
+    pub fn keytab(&self) -> Option<&Path> {
+        self.keytab.as_deref()
+    }
+
+    /// The path of the keytab credentials are acquired from, if it is a file.
+    pub fn keytab_path(&self) -> Option<PathBuf> {
+        Keytab::resolve_path(self.keytab())
+    }
+
+    /// Reads the keytab, replacing the cached keys. Returns `None` if the keytab isn't a file.
+    /// The keys of the last successful read are kept if the keytab can't be read, e.g. while
+    /// it is being rewritten.
+    pub fn load_keytab(&self) -> Result<Option<Arc<Keytab>>, KeytabError> {
+        let path = if let Some(p) = self.keytab_path() {
+            p
+        } else {
+            return Ok(None);
+        };
+        let keytab = Arc::new(Keytab::read(&path)?);
+        if let Ok(mut keys) = self.keys.write() {
+            *keys = Some(keytab.clone());
+        }
+        Ok(Some(keytab))
+    }
+
+    /// The keys of the keytab as of the last time it was read, if that succeeded.
+    pub fn keytab_keys(&self) -> Option<Arc<Keytab>> {
+        self.keys.read().ok()?.clone()
+    }
+
+    /// Acquires a fresh credential, replacing the cached one.
+    pub fn acquire(&self) -> Result<Cred, Error> {
+        let cred = self.acquire_uncached()?;
+        self.cached.replace(cred.clone());
+        Ok(cred)
+    }
+
+    fn acquire_uncached(&self) -> Result<Cred, Error> {
+        if self.store.is_empty() {
+            Cred::acquire(
+                self.name.as_ref(),
+                None,
+                self.usage,
+                self.desired_mechs.as_ref(),
+            )
+        } else {
+            self.acquire_from_store()
+        }
+    }
+
+    /// Acquires a credential with `gss_acquire_cred_from`, which libgssapi doesn't wrap.
+    fn acquire_from_store(&self) -> Result<Cred, Error> {
+        let mut elements = self.store_elements();
+        let store = gss_key_value_set_desc {
+            count: elements.len() as u32,
+            elements: elements.as_mut_ptr(),
+        };
+        let usage = match self.usage {
+            CredUsage::Accept => GSS_C_ACCEPT,
+            CredUsage::Initiate => GSS_C_INITIATE,
+            CredUsage::Both => GSS_C_BOTH,
+        };
+
+        let mut minor = 0;
+        let mut cred = ptr::null_mut();
+        let major = unsafe {
+            gss_acquire_cred_from(
+                &mut minor,
+                self.name.as_ref().map_or(ptr::null_mut(), |n| n.to_c()),
+                GSS_C_INDEFINITE,
+                self.desired_mechs
+                    .as_ref()
+                    .map_or(ptr::null_mut(), |m| m.to_c()),
+                usage,
+                &store,
+                &mut cred,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+        if major == GSS_S_COMPLETE {
+            Ok(unsafe { Cred::from_c(cred) })
+        } else {
+            Err(Error {
+                major: MajorFlags::from_bits_truncate(major),
+                minor,
+            })
+        }
+    }
+
+    /// The credential store as passed to `gss_acquire_cred_from`, borrowing from `self.store`.
+    fn store_elements(&self) -> Vec<gss_key_value_element_desc> {
+        self.store
+            .iter()
+            .map(|(key, value)| gss_key_value_element_desc {
+                key: key.as_ptr(),
+                value: value.as_ptr(),
+            })
+            .collect()
+    }
+
+    /// Checks the configuration step by step, ending up with a freshly acquired credential.
+    ///
+    /// The service name must canonicalize, credentials must be acquirable for it, and every
+    /// desired mechanism must be supported by those credentials.
+    pub fn validate(&self) -> Result<Cred, ConfigError> {
+        if let Some(name) = &self.name {
+            name.canonicalize(name_mech(self.desired_mechs.as_ref()))
+                .map_err(|error| ConfigError::Canonicalize {
+                    name: name.to_string(),
+                    error,
+                })?;
+        }
+
+        let cred = self.acquire().map_err(|error| ConfigError::AcquireCred {
+            name: self.name.as_ref().map(|n| n.to_string()),
+            error,
+        })?;
+        self.check_mechs(&cred)?;
+        Ok(cred)
+    }
+
+    fn check_mechs(&self, cred: &Cred) -> Result<(), ConfigError> {
+        if let Some(desired) = &self.desired_mechs {
+            let info = cred.info().map_err(ConfigError::CredInfo)?;
+            for mech in desired.iter() {
+                if !info.mechanisms.contains(mech).unwrap_or(false) {
+                    return Err(ConfigError::UnsupportedMech {
+                        mech: mech.to_string(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Acquires and validates a new credential, only replacing the cached one if it is usable.
+    pub fn reload(&self) -> Result<(), ConfigError> {
+        let cred = self
+            .acquire_uncached()
+            .map_err(|error| ConfigError::AcquireCred {
+                name: self.name.as_ref().map(|n| n.to_string()),
+                error,
+            })?;
+        self.check_mechs(&cred)?;
+        self.cached.replace(cred);
+        Ok(())
+    }
+
+    /// Returns the cached credential, acquiring a new one if there is none or if it is due for
+    /// a refresh. Should the refresh fail, the old credential is kept in use.
+    pub fn get(&self) -> Result<Cred, Error> {
+        self.cached.get(|stale| {
+            let cred = self.acquire_uncached();
+            match &cred {
+                Ok(_) if stale => info!("Kerberos: Refreshed acceptor credentials"),
+                Err(e) if stale => {
+                    warn!("Kerberos: Failed to refresh credentials, keeping old: {}", e)
+                }
+                _ => {}
+            }
+            cred
+        })
+    }
+
+    /// Drops the cached credential if `e` indicates that it is no longer usable, so it is
+    /// re-acquired for the next negotiation.
+    pub fn invalidate_on(&self, e: &Error) {
+        let stale = MajorFlags::GSS_S_NO_CRED
+            | MajorFlags::GSS_S_CREDENTIALS_EXPIRED
+            | MajorFlags::GSS_S_DEFECTIVE_CREDENTIAL;
+        if e.major.intersects(stale) {
+            warn!("Kerberos: Acceptor credentials rejected, re-acquiring: {}", e);
+            self.cached.clear();
+        }
+    }
+}
+
+/// The mechanism to canonicalize the service name against: Kerberos if it is desired or wrapped
+/// by a desired SPNEGO or IAKERB, else the first desired mechanism.
+fn name_mech(desired: Option<&OidSet>) -> Option<&Oid> {
+    let desired = if let Some(d) = desired {
+        d
+    } else {
+        return Some(&GSS_MECH_KRB5);
+    };
+    let krb5 = [&GSS_MECH_KRB5, &GSS_MECH_SPNEGO, &GSS_MECH_IAKERB]
+        .iter()
+        .any(|m| desired.contains(m).unwrap_or(false));
+    if krb5 {
+        Some(&GSS_MECH_KRB5)
+    } else {
+        desired.iter().next()
+    }
+}
+
+/// Polls the modification time of the keytab, reloading the credentials and keys of `creds`
+/// whenever it changes, until Rocket shuts down or the fairing is dropped.
+pub(crate) async fn watch_keytab(creds: Weak<CredCache>, interval: Duration, shutdown: Shutdown) {
+    let (path, principal, keys) = match creds.upgrade() {
+        Some(c) => (c.keytab_path(), c.principal(), c.keytab_keys()),
+        None => return,
+    };
+    let path = if let Some(p) = path {
+        p
+    } else {
+        info!("Kerberos: Keytab is not a file, not watching it for changes");
+        return;
+    };
+
+    let kvno =
+        move |keys: Option<Arc<Keytab>>| describe_kvno(keys.as_deref(), principal.as_deref());
+    let mut last_kvno = kvno(keys);
+
+    let watched = path.clone();
+    watch_file(watched, interval, shutdown, move || {
+        let creds = if let Some(c) = creds.upgrade() {
+            c
+        } else {
+            return false;
+        };
+        if let Err(e) = creds.load_keytab() {
+            debug!("Kerberos: Failed to read keytab {}: {}", path.display(), e);
+        }
+        match creds.reload() {
+            Ok(()) => {
+                let now_kvno = kvno(creds.keytab_keys());
+                info!(
+                    "Kerberos: Keytab {} changed, reloaded credentials, kvno {} -> {}",
+                    path.display(),
+                    last_kvno,
+                    now_kvno
+                );
+                last_kvno = now_kvno;
+            }
+            Err(e) => warn!(
+                "Kerberos: Keytab {} changed, keeping old credentials: {}",
+                path.display(),
+                e
+            ),
+        }
+        true
+    })
+    .await;
+}
+
+/// The highest kvno of `principal` in `keys` for logging, `unknown` if there is none.
+fn describe_kvno(keys: Option<&Keytab>, principal: Option<&str>) -> String {
+    keys.and_then(|k| k.max_kvno(principal))
+        .map_or("unknown".to_string(), |k| k.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keytab::tests::{encode, Entry};
+    use std::ffi::CStr;
+    use std::fs;
+
+    fn store(creds: &CredCache) -> Vec<(String, String)> {
+        let string = |s| unsafe { CStr::from_ptr(s) }.to_string_lossy().into_owned();
+        creds
+            .store_elements()
+            .iter()
+            .map(|e| (string(e.key), string(e.value)))
+            .collect()
+    }
+
+    #[test]
+    fn builds_the_credential_store() {
+        let mut creds = CredCache::new(None, None, CredUsage::Accept);
+        assert!(store(&creds).is_empty());
+
+        let keytab = PathBuf::from("/srv/http.keytab");
+        creds
+            .set_store(Some(keytab.clone()), Some("FILE:/run/http.ccache".into()))
+            .unwrap();
+        assert_eq!(
+            store(&creds),
+            [
+                ("keytab".to_string(), "/srv/http.keytab".to_string()),
+                ("ccache".to_string(), "FILE:/run/http.ccache".to_string()),
+            ]
+        );
+        assert_eq!(creds.keytab(), Some(keytab.as_path()));
+        assert_eq!(creds.keytab_path(), Some(keytab));
+
+        creds.set_store(None, Some("KCM:".into())).unwrap();
+        assert_eq!(store(&creds), [("ccache".to_string(), "KCM:".to_string())]);
+        assert_eq!(creds.keytab(), None);
+    }
+
+    #[test]
+    fn rejects_nul_in_the_credential_store() {
+        let mut creds = CredCache::new(None, None, CredUsage::Accept);
+        creds.set_store(Some("/srv/http.keytab".into()), None).unwrap();
+        assert!(creds.set_store(Some("/srv/\0.keytab".into()), None).is_err());
+        assert!(creds.set_store(None, Some("FILE:\0".into())).is_err());
+        // A rejected store leaves the previous one in place
+        assert_eq!(store(&creds).len(), 1);
+    }
+
+    #[test]
+    fn keeps_keys_of_the_last_readable_keytab() {
+        let path = std::env::temp_dir()
+            .join(format!("rocket-gssapi-cred-keytab-{}", std::process::id()));
+        let keytab = |kvno| encode(2, false, &[Entry::new(&["HTTP", "web", "CORP"], kvno, 18)]);
+        let mut creds = CredCache::new(None, None, CredUsage::Accept);
+        creds.set_store(Some(path.clone()), None).unwrap();
+        let kvno = |creds: &CredCache| describe_kvno(creds.keytab_keys().as_deref(), None);
+
+        assert!(creds.load_keytab().is_err());
+        assert_eq!(kvno(&creds), "unknown");
+
+        fs::write(&path, keytab(6)).unwrap();
+        assert!(creds.load_keytab().unwrap().is_some());
+        assert_eq!(kvno(&creds), "6");
+
+        // Caught halfway through being rewritten
+        fs::write(&path, &keytab(7)[..20]).unwrap();
+        assert!(creds.load_keytab().is_err());
+        assert_eq!(kvno(&creds), "6");
+
+        fs::write(&path, keytab(7)).unwrap();
+        assert!(creds.load_keytab().is_ok());
+        let _ = fs::remove_file(&path);
+        assert_eq!(kvno(&creds), "7");
+    }
+
+    #[test]
+    fn describes_the_kvno_of_the_principal() {
+        let keytab = Keytab::parse(&encode(
+            2,
+            false,
+            &[
+                Entry::new(&["HTTP", "web", "CORP"], 6, 18),
+                Entry::new(&["HTTP", "web", "CORP"], 7, 18),
+                Entry::new(&["host", "web", "CORP"], 9, 18),
+            ],
+        ))
+        .unwrap();
+        assert_eq!(describe_kvno(Some(&keytab), Some("HTTP/web@CORP")), "7");
+        assert_eq!(describe_kvno(Some(&keytab), None), "9");
+        assert_eq!(describe_kvno(Some(&keytab), Some("HTTP/www@CORP")), "unknown");
+        assert_eq!(describe_kvno(None, Some("HTTP/web@CORP")), "unknown");
+    }
+}
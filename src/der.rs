@@ -0,0 +1,177 @@
+//! Minimal reader for the DER encoding used by GSSAPI, SPNEGO and Kerberos tokens.
+
+/// A single tag-length-value element.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Tlv<'a> {
+    pub tag: u8,
+    pub value: &'a [u8],
+}
+
+/// Reads the element at the start of `buf`, returning it and the bytes following it. Only
+/// single-byte tags and definite lengths are supported, which is all these protocols use.
+pub(crate) fn read(buf: &[u8]) -> Option<(Tlv<'_>, &[u8])> {
+    let (&tag, rest) = buf.split_first()?;
+    if tag & 0x1f == 0x1f {
+        return None;
+    }
+    let (&first, mut rest) = rest.split_first()?;
+    let len = if first & 0x80 == 0 {
+        first as usize
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 || rest.len() < count {
+            return None;
+        }
+        let (bytes, r) = rest.split_at(count);
+        rest = r;
+        bytes.iter().fold(0usize, |a, &b| a << 8 | b as usize)
+    };
+    if rest.len() < len {
+        return None;
+    }
+    let (value, rest) = rest.split_at(len);
+    Some((Tlv { tag, value }, rest))
+}
+
+/// Reads the element at the start of `buf` if it has the tag `tag`, returning its value and
+/// the bytes following it.
+pub(crate) fn expect(buf: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    match read(buf)? {
+        (t, rest) if t.tag == tag => Some((t.value, rest)),
+        _ => None,
+    }
+}
+
+/// Decodes the arcs of an OBJECT IDENTIFIER value.
+pub(crate) fn oid_arcs(value: &[u8]) -> Option<Vec<u64>> {
+    let mut arcs = Vec::new();
+    let mut arc = 0u64;
+    for (i, &b) in value.iter().enumerate() {
+        if arc >> 57 != 0 {
+            return None;
+        }
+        arc = arc << 7 | (b & 0x7f) as u64;
+        if b & 0x80 == 0 {
+            if arcs.is_empty() {
+                // The first two arcs share a subidentifier
+                let first = (arc / 40).min(2);
+                arcs.push(first);
+                arcs.push(arc - first * 40);
+            } else {
+                arcs.push(arc);
+            }
+            arc = 0;
+        } else if i == value.len() - 1 {
+            return None;
+        }
+    }
+    (!arcs.is_empty()).then_some(arcs)
+}
+
+/// Decodes an INTEGER value of up to 8 bytes.
+pub(crate) fn integer(value: &[u8]) -> Option<i64> {
+    if value.is_empty() || value.len() > 8 {
+        return None;
+    }
+    let init = if value[0] & 0x80 != 0 { -1 } else { 0 };
+    Some(value.iter().fold(init, |a, &b| a << 8 | b as i64))
+}
+
+/// Encodes an element, for building tokens in tests.
+#[cfg(test)]
+pub(crate) fn tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut buf = vec![tag];
+    match value.len() {
+        len @ 0..=0x7f => buf.push(len as u8),
+        len => {
+            let bytes = len.to_be_bytes();
+            let skip = bytes.iter().take_while(|&&b| b == 0).count();
+            buf.push(0x80 | (bytes.len() - skip) as u8);
+            buf.extend(&bytes[skip..]);
+        }
+    }
+    buf.extend(value);
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_short_and_long_lengths() {
+        let (t, rest) = read(&[0x04, 0x02, 0xaa, 0xbb, 0xcc]).unwrap();
+        assert_eq!(
+            t,
+            Tlv {
+                tag: 0x04,
+                value: &[0xaa, 0xbb]
+            }
+        );
+        assert_eq!(rest, [0xcc]);
+
+        let value = vec![0x55; 0x1234];
+        let encoded = tlv(0x04, &value);
+        assert_eq!(encoded[..4], [0x04, 0x82, 0x12, 0x34]);
+        let (t, rest) = read(&encoded).unwrap();
+        assert_eq!(t.value, value.as_slice());
+        assert!(rest.is_empty());
+        // Long form need not be minimal
+        assert_eq!(read(&[0x04, 0x81, 0x01, 0xaa]).unwrap().0.value, [0xaa]);
+        assert!(read(&[0x05, 0x00]).unwrap().0.value.is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_elements() {
+        // Empty, no length, high tag numbers and the indefinite length
+        assert_eq!(read(&[]), None);
+        assert_eq!(read(&[0x04]), None);
+        assert_eq!(read(&[0x1f, 0x01, 0x00]), None);
+        assert_eq!(read(&[0x30, 0x80, 0x00, 0x00]), None);
+        // Lengths of more than four bytes, or running past the buffer
+        assert_eq!(read(&[0x04, 0x85, 0, 0, 0, 0, 1, 0xaa]), None);
+        assert_eq!(read(&[0x04, 0x82, 0x01]), None);
+        assert_eq!(read(&[0x04, 0x03, 0xaa, 0xbb]), None);
+        assert_eq!(read(&[0x04, 0x84, 0xff, 0xff, 0xff, 0xff, 0xaa]), None);
+    }
+
+    #[test]
+    fn expects_tags() {
+        let buf = [tlv(0x06, &[0x2a]), tlv(0x04, &[])].concat();
+        let (oid, rest) = expect(&buf, 0x06).unwrap();
+        assert_eq!(oid, [0x2a]);
+        assert_eq!(expect(rest, 0x04), Some((&[][..], &[][..])));
+        assert_eq!(expect(&buf, 0x04), None);
+    }
+
+    #[test]
+    fn decodes_oids() {
+        let krb5 = [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x12, 0x01, 0x02, 0x02];
+        assert_eq!(oid_arcs(&krb5).unwrap(), [1, 2, 840, 113554, 1, 2, 2]);
+        assert_eq!(oid_arcs(&[0x2b, 0x06, 0x01]).unwrap(), [1, 3, 6, 1]);
+        // Arcs under 2 may exceed 39
+        assert_eq!(oid_arcs(&[0x88, 0x37, 0x03]).unwrap(), [2, 999, 3]);
+        assert_eq!(oid_arcs(&[]), None);
+        // A subidentifier cut off in its continuation bytes
+        assert_eq!(oid_arcs(&[0x2a, 0x86]), None);
+        // A subidentifier beyond 64 bits
+        assert_eq!(
+            oid_arcs(&[0x2a, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f]),
+            None
+        );
+    }
+
+    #[test]
+    fn decodes_integers() {
+        assert_eq!(integer(&[0x05]), Some(5));
+        assert_eq!(integer(&[0x00, 0xff]), Some(255));
+        assert_eq!(integer(&[0xff]), Some(-1));
+        assert_eq!(integer(&[0x96, 0xc7, 0x39, 0x25]), Some(-1765328603));
+        assert_eq!(
+            integer(&[0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
+            Some(i64::MAX)
+        );
+        assert_eq!(integer(&[]), None);
+        assert_eq!(integer(&[0; 9]), None);
+    }
+}
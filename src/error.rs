@@ -0,0 +1,212 @@
+use crate::group::GroupFileError;
+use crate::guard::GssapiAuth;
+use crate::policy::PolicyError;
+use crate::token::TokenKind;
+use libgssapi::error::Error;
+use rocket::form::Shareable;
+use rocket::http::Status;
+use rocket::Request;
+use std::fmt;
+
+/// The `KRB5KRB_AP_ERR_SKEW` minor status, reported when client and server clocks differ too
+/// much.
+const KRB5KRB_AP_ERR_SKEW: u32 = -1765328347i32 as u32;
+
+/// Why a request could not be authenticated, available to routes through a
+/// `Result<GssapiAuth, GssapiError>` guard and to catchers through [`GssapiError::of()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GssapiError {
+    /// The client did not send an `Authorization: Negotiate` header.
+    NoHeader,
+    /// The client could not be identified by the fairing's identifier.
+    Unidentified,
+    /// The `Authorization: Negotiate` header was not valid base64.
+    Malformed,
+    /// The acceptor credentials could not be acquired.
+    Credentials(String),
+    /// The token is of a kind rejected by the fairing's [`TokenPolicy`], e.g. NTLM.
+    ///
+    /// [`TokenPolicy`]: crate::TokenPolicy
+    UnsupportedToken(TokenKind),
+    /// The client and server clocks differ by more than the allowed skew.
+    ClockSkew,
+    /// The client token was rejected by the mechanism.
+    InvalidToken(String),
+    /// The context lacks services the fairing requires, e.g. `mutual, integrity`.
+    MissingFlags(String),
+    /// The context uses services the fairing forbids, e.g. `anonymous`.
+    ForbiddenFlags(String),
+    /// The client authenticated, but its realm is not accepted by the fairing.
+    RealmRejected(String),
+    /// The client authenticated, but is denied by the principal policy.
+    PolicyDenied(String),
+    /// The client authenticated, but does not meet the requirements of the route.
+    Forbidden(String),
+    /// The group membership of the client could not be looked up.
+    GroupLookup(String),
+    /// The client authenticated, but does not map to a local account.
+    NoLocalAccount(String),
+    /// The local account of the client could not be looked up.
+    AccountLookup(String),
+    /// The negotiation needs another leg, the response carries the server token.
+    ContinueNeeded,
+    /// The context was shed because of too many concurrent negotiations.
+    Overloaded,
+}
+impl GssapiError {
+    /// Classifies a failure to work a context.
+    pub(crate) fn from_step(e: &Error) -> GssapiError {
+        if e.minor == KRB5KRB_AP_ERR_SKEW {
+            GssapiError::ClockSkew
+        } else {
+            GssapiError::InvalidToken(e.to_string())
+        }
+    }
+
+    /// The status the [`GssapiAuth`] guard and the guards built on it fail with:
+    /// `403 Forbidden` for clients that authenticated but are not let in, as challenging them
+    /// again would only make browsers retry the negotiation in a loop, `500 Internal Server
+    /// Error` when the server's credentials or a lookup failed, `401 Unauthorized` otherwise.
+    pub fn status(&self) -> Status {
+        match self {
+            GssapiError::MissingFlags(_)
+            | GssapiError::ForbiddenFlags(_)
+            | GssapiError::RealmRejected(_)
+            | GssapiError::PolicyDenied(_)
+            | GssapiError::Forbidden(_)
+            | GssapiError::NoLocalAccount(_) => Status::Forbidden,
+            GssapiError::Credentials(_)
+            | GssapiError::GroupLookup(_)
+            | GssapiError::AccountLookup(_) => Status::InternalServerError,
+            _ => Status::Unauthorized,
+        }
+    }
+
+    /// Returns why `req` could not be authenticated, or `None` if it was. Useful in catchers,
+    /// which don't have access to guard errors.
+    pub fn of<'r>(req: &'r Request<'_>) -> Option<&'r GssapiError> {
+        if req.local_cache(GssapiAuth::default).complete {
+            None
+        } else {
+            Some(req.local_cache(|| GssapiError::NoHeader))
+        }
+    }
+}
+
+impl fmt::Display for GssapiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GssapiError::NoHeader => write!(f, "no Negotiate authorization was sent"),
+            GssapiError::Unidentified => write!(f, "the client could not be identified"),
+            GssapiError::Malformed => write!(f, "the Negotiate authorization is malformed"),
+            GssapiError::Credentials(e) => write!(f, "server credentials unavailable: {}", e),
+            GssapiError::UnsupportedToken(kind) => write!(f, "{} tokens are not accepted", kind),
+            GssapiError::ClockSkew => write!(f, "client and server clocks differ too much"),
+            GssapiError::InvalidToken(e) => write!(f, "the token was rejected: {}", e),
+            GssapiError::MissingFlags(flags) => {
+                write!(f, "the context lacks required services: {}", flags)
+            }
+            GssapiError::ForbiddenFlags(flags) => {
+                write!(f, "the context uses forbidden services: {}", flags)
+            }
+            GssapiError::RealmRejected(p) => {
+                write!(f, "principals from the realm of {} are not accepted", p)
+            }
+            GssapiError::PolicyDenied(p) => write!(f, "{} is denied by policy", p),
+            GssapiError::Forbidden(p) => write!(f, "{} may not access this route", p),
+            GssapiError::GroupLookup(e) => write!(f, "group membership lookup failed: {}", e),
+            GssapiError::NoLocalAccount(p) => write!(f, "{} has no local account", p),
+            GssapiError::AccountLookup(e) => write!(f, "local account lookup failed: {}", e),
+            GssapiError::ContinueNeeded => write!(f, "the negotiation is still in progress"),
+            GssapiError::Overloaded => write!(f, "too many negotiations in progress"),
+        }
+    }
+}
+
+impl std::error::Error for GssapiError {}
+
+unsafe impl Shareable for GssapiError {
+    fn size(&self) -> usize {
+        size_of_val(self)
+    }
+}
+
+/// Describes which step of validating the GSSAPI configuration failed, either when building
+/// the fairing or on ignite.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// Credentials for `CredUsage::Initiate` can't accept contexts from clients.
+    InvalidUsage,
+    /// An option was given an invalid value, or options were combined in an invalid way.
+    InvalidOption(&'static str),
+    /// The configured service principal could not be imported as a GSSAPI name.
+    Name { name: String, error: Error },
+    /// The set of configured mechanisms could not be built.
+    Mechanisms(Error),
+    /// The service principal could not be canonicalized, it is likely malformed.
+    Canonicalize { name: String, error: Error },
+    /// Acceptor credentials could not be acquired, the keytab is likely missing or lacks the
+    /// service principal.
+    AcquireCred { name: Option<String>, error: Error },
+    /// The acquired credentials could not be inspected.
+    CredInfo(Error),
+    /// A desired mechanism is not supported by the acquired credentials.
+    UnsupportedMech { mech: String },
+    /// The principal policy file could not be loaded.
+    Policy(PolicyError),
+    /// The group file could not be loaded.
+    Groups(GroupFileError),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::InvalidUsage => {
+                write!(f, "credential usage must be Accept or Both for a server")
+            }
+            ConfigError::InvalidOption(reason) => write!(f, "invalid option: {}", reason),
+            ConfigError::Name { name, error } => {
+                write!(f, "failed to import service name {}: {}", name, error)
+            }
+            ConfigError::Mechanisms(error) => {
+                write!(f, "failed to build the set of mechanisms: {}", error)
+            }
+            ConfigError::Canonicalize { name, error } => {
+                write!(f, "failed to canonicalize service name {}: {}", name, error)
+            }
+            ConfigError::AcquireCred { name, error } => write!(
+                f,
+                "failed to acquire acceptor credentials for {}: {}",
+                name.as_deref().unwrap_or("the default principal"),
+                error
+            ),
+            ConfigError::CredInfo(error) => {
+                write!(f, "failed to inspect acceptor credentials: {}", error)
+            }
+            ConfigError::UnsupportedMech { mech } => write!(
+                f,
+                "mechanism {} is not supported by the acceptor credentials",
+                mech
+            ),
+            ConfigError::Policy(error) => write!(f, "{}", error),
+            ConfigError::Groups(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Name { error, .. }
+            | ConfigError::Mechanisms(error)
+            | ConfigError::Canonicalize { error, .. }
+            | ConfigError::AcquireCred { error, .. }
+            | ConfigError::CredInfo(error) => Some(error),
+            ConfigError::Policy(error) => Some(error),
+            ConfigError::Groups(error) => Some(error),
+            ConfigError::InvalidUsage
+            | ConfigError::InvalidOption(_)
+            | ConfigError::UnsupportedMech { .. } => None,
+        }
+    }
+}
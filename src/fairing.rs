@@ -1,49 +1,430 @@
+use crate::accept::{AcceptCtx, AcceptError};
+use crate::builder::GssapiFairingBuilder;
+use crate::config::{ContextFlag, GssapiConfig};
+use crate::cred::{watch_keytab, CredCache};
+use crate::error::{ConfigError, GssapiError};
+use crate::group::{FileGroupResolver, GroupResolver, Resolver};
 use crate::guard::GssapiAuth;
+use crate::krb5::ApReq;
+use crate::policy::PrincipalPolicy;
+use crate::realm::RealmPolicy;
+use crate::spnego::NegToken;
+use crate::store::{ContextLimits, ContextStore, MemoryStore, PendingContext};
+use crate::token::{TokenAction, TokenKind, TokenPolicy};
+use crate::watch::watch_file;
 use base64::prelude::*;
-use libgssapi::context::{SecurityContext, ServerCtx};
-use libgssapi::credential::{Cred, CredUsage};
+use libgssapi::context::CtxFlags;
+use libgssapi::credential::CredUsage;
 use libgssapi::name::Name;
 use libgssapi::oid::OidSet;
-use rocket::fairing::{Fairing, Info, Kind};
+use rocket::fairing::{self, AdHoc, Fairing, Info, Kind};
 use rocket::form::Shareable;
 use rocket::http::{Header, Status};
-use rocket::{error, info, warn, Data, Orbit, Request, Response, Rocket};
-use std::collections::HashMap;
+use rocket::{debug, error, info, warn, Build, Data, Orbit, Request, Response, Rocket};
 use std::ops::Deref;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-type ContextStore = HashMap<String, Arc<Mutex<ServerCtx>>>;
-type IdentifierFunction = dyn Fn(&mut Request) -> Option<String> + Send + Sync;
+/// How often a mismatch between client tickets and the keytab is logged as a warning.
+const MISMATCH_WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+pub(crate) type IdentifierFunction = dyn Fn(&mut Request) -> Option<String> + Send + Sync;
+
+/// Controls which responses carry a `WWW-Authenticate: Negotiate` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResponseBehavior {
+    /// Challenge clients on `401 Unauthorized` responses, triggering a negotiation.
+    pub challenge: bool,
+    /// Return the final server token on `200 OK` responses, letting the client authenticate
+    /// the server.
+    pub final_token: bool,
+}
+impl Default for ResponseBehavior {
+    fn default() -> Self {
+        ResponseBehavior {
+            challenge: true,
+            final_token: true,
+        }
+    }
+}
 
 pub struct GssapiFairing {
-    name: Option<Name>,
-    desired_mechs: Option<OidSet>,
-    identifier: Box<IdentifierFunction>,
-    // todo: implement method to prune these two
-    contexts: Arc<Mutex<ContextStore>>,
-    usage: CredUsage,
+    pub(crate) creds: Arc<CredCache>,
+    pub(crate) identifier: Box<IdentifierFunction>,
+    pub(crate) contexts: Arc<dyn ContextStore>,
+    pub(crate) limits: ContextLimits,
+    pub(crate) sweep_interval: Duration,
+    pub(crate) keytab_poll_interval: Duration,
+    pub(crate) policy_poll_interval: Duration,
+    pub(crate) realms: RealmPolicy,
+    pub(crate) policy: Option<Arc<PrincipalPolicy>>,
+    pub(crate) response: ResponseBehavior,
+    pub(crate) groups: Option<Arc<dyn GroupResolver>>,
+    pub(crate) required_flags: CtxFlags,
+    pub(crate) forbidden_flags: CtxFlags,
+    pub(crate) tokens: TokenPolicy,
+    pub(crate) mismatch_warned: Mutex<Option<Instant>>,
 }
 impl GssapiFairing {
     /// Creates a Kerberos fairing, setting up it's use for the GssapiAuth guard
     ///
     /// Takes a GSSAPI name and supported GSSAPI mechanisms as arguments. Set to None to use
     /// system defaults(?)
+    #[deprecated(note = "use `GssapiFairing::builder()` instead")]
     pub fn new(name: Option<Name>, desired_mechs: Option<OidSet>, usage: CredUsage) -> GssapiFairing {
         GssapiFairing {
-            name,
-            desired_mechs,
+            creds: Arc::new(CredCache::new(name, desired_mechs, usage)),
             identifier: Box::new(|r| r.client_ip().map(|ip| ip.to_string())),
-            contexts: Arc::new(Mutex::new(ContextStore::default())),
-            usage,
+            contexts: Arc::new(MemoryStore::new()),
+            limits: ContextLimits::default(),
+            sweep_interval: Duration::from_secs(10),
+            keytab_poll_interval: Duration::from_secs(30),
+            policy_poll_interval: Duration::from_secs(30),
+            realms: RealmPolicy::default(),
+            policy: None,
+            response: ResponseBehavior::default(),
+            groups: None,
+            required_flags: CtxFlags::empty(),
+            forbidden_flags: CtxFlags::empty(),
+            tokens: TokenPolicy::default(),
+            mismatch_warned: Mutex::new(None),
         }
     }
 
+    /// Starts building a Kerberos fairing, with system defaults for the service principal and
+    /// mechanisms.
+    pub fn builder() -> GssapiFairingBuilder {
+        GssapiFairingBuilder::default()
+    }
+
+    /// Creates a fairing configured from the `gssapi` section of the Rocket configuration,
+    /// see [`GssapiConfig`] for the available keys. Launch is aborted if the section is
+    /// invalid.
+    pub fn fairing() -> impl Fairing {
+        AdHoc::try_on_ignite("Kerberos Configuration", |rocket| async move {
+            let config = match rocket.figment().extract_inner::<GssapiConfig>("gssapi") {
+                Ok(c) => c,
+                Err(e) if e.missing() => GssapiConfig::default(),
+                Err(e) => {
+                    error!("Kerberos: Failed to read gssapi configuration: {}", e);
+                    return Err(rocket);
+                }
+            };
+            match GssapiFairing::from_config(&config) {
+                Ok(f) => Ok(rocket.attach(f)),
+                Err(e) => {
+                    error!("Kerberos: Invalid configuration: {}", e);
+                    Err(rocket)
+                }
+            }
+        })
+    }
+
+    /// Creates a fairing from an already extracted [`GssapiConfig`].
+    pub fn from_config(config: &GssapiConfig) -> Result<GssapiFairing, ConfigError> {
+        let mut builder = GssapiFairing::builder();
+        if let Some(p) = &config.principal {
+            let name = Name::new(p.as_bytes(), Some(config.name_type.oid())).map_err(|error| {
+                ConfigError::Name {
+                    name: p.clone(),
+                    error,
+                }
+            })?;
+            builder = builder.principal(name);
+        }
+
+        if !config.mechanisms.is_empty() {
+            let mut mechs = OidSet::new().map_err(ConfigError::Mechanisms)?;
+            for m in &config.mechanisms {
+                mechs.add(m.oid()).map_err(ConfigError::Mechanisms)?;
+            }
+            builder = builder.mechanisms(mechs);
+        }
+
+        if let Some(keytab) = &config.keytab {
+            builder = builder.keytab(keytab);
+        }
+        if let Some(ccache) = &config.ccache {
+            builder = builder.ccache(ccache);
+        }
+
+        if let Some(policy) = &config.policy {
+            builder = builder.policy(PrincipalPolicy::load(policy).map_err(ConfigError::Policy)?);
+        }
+        if let Some(groups) = &config.groups {
+            builder = builder
+                .group_resolver(FileGroupResolver::load(groups).map_err(ConfigError::Groups)?);
+        }
+
+        let flags = |f: &[ContextFlag]| f.iter().fold(CtxFlags::empty(), |a, f| a | f.flag());
+        // A negotiation may take several legs, each up to `context_timeout`
+        let idle_timeout = Duration::from_secs(config.context_timeout);
+        let negotiation_timeout = idle_timeout.max(ContextLimits::default().negotiation_timeout);
+        let identifier = config.identifier.clone();
+        builder
+            .required_flags(flags(&config.required_flags))
+            .forbidden_flags(flags(&config.forbidden_flags))
+            .token_policy(TokenPolicy {
+                ntlm: config.ntlm,
+                raw_kerberos: config.raw_kerberos,
+                ..TokenPolicy::default()
+            })
+            .accepted_realms(RealmPolicy::new(&config.realms))
+            .idle_timeout(idle_timeout)
+            .negotiation_timeout(negotiation_timeout)
+            .keytab_poll_interval(Duration::from_secs(config.keytab_poll_interval))
+            .policy_poll_interval(Duration::from_secs(config.policy_poll_interval))
+            .identifier(move |r| identifier.identify(r))
+            .build()
+    }
+
+    /// Checks the service name, credentials and mechanisms like on ignite, without launching
+    /// Rocket.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.creds.validate().map(|_| ())
+    }
+
     /// By default, the `Request::client_ip()` result is used to identify clients in order to
     /// work their SecurityContexts to completion. If you instead want to use a different method
     /// to identify clients you can set it here.
+    #[deprecated(note = "use `GssapiFairingBuilder::identifier` instead")]
     pub fn set_identifier(&mut self, identifier: &'static IdentifierFunction) {
         self.identifier = Box::new(identifier);
     }
+
+    /// Sets how long a half-finished context is kept waiting for the client to send the next
+    /// token before it is evicted. Defaults to 30 seconds.
+    #[deprecated(note = "use `GssapiFairingBuilder::idle_timeout` instead")]
+    pub fn set_idle_timeout(&mut self, timeout: Duration) {
+        self.limits.idle_timeout = timeout;
+    }
+
+    /// Sets the maximum time a negotiation may take from the first token to completion,
+    /// regardless of how active the client is. Defaults to 2 minutes.
+    #[deprecated(note = "use `GssapiFairingBuilder::negotiation_timeout` instead")]
+    pub fn set_negotiation_timeout(&mut self, timeout: Duration) {
+        self.limits.negotiation_timeout = timeout;
+    }
+
+    /// Sets how often the background sweeper started on liftoff prunes stale contexts.
+    /// Defaults to 10 seconds.
+    #[deprecated(note = "use `GssapiFairingBuilder::sweep_interval` instead")]
+    pub fn set_sweep_interval(&mut self, interval: Duration) {
+        self.sweep_interval = interval;
+    }
+
+    /// Sets the maximum number of half-finished contexts kept at once. When the limit is
+    /// reached, the least recently used contexts are shed to make room. Defaults to 1024.
+    #[deprecated(note = "use `GssapiFairingBuilder::max_contexts` instead")]
+    pub fn set_max_contexts(&mut self, max: usize) {
+        self.limits.max_contexts = max;
+    }
+
+    /// Sets the maximum number of concurrent negotiations from a single client IP address.
+    /// Negotiations beyond the limit are refused. This only has an effect if the identifier
+    /// is more specific than the client IP. Defaults to 4.
+    #[deprecated(note = "use `GssapiFairingBuilder::max_contexts_per_client` instead")]
+    pub fn set_max_contexts_per_client(&mut self, max: usize) {
+        self.limits.max_contexts_per_client = max;
+    }
+
+    /// Sets how often the acceptor credentials are re-acquired from the keytab. Credentials are
+    /// also re-acquired whenever a context fails due to them. Defaults to 1 hour.
+    #[deprecated(note = "use `GssapiFairingBuilder::cred_refresh_interval` instead")]
+    pub fn set_cred_refresh_interval(&mut self, interval: Duration) {
+        self.creds.set_refresh_interval(interval);
+    }
+
+    /// Restricts the realms clients may authenticate from, see [`RealmPolicy`] for the
+    /// patterns. By default all realms trusted by the KDC are accepted.
+    #[deprecated(note = "use `GssapiFairingBuilder::accepted_realms` instead")]
+    pub fn set_accepted_realms(&mut self, realms: RealmPolicy) {
+        self.realms = realms;
+    }
+
+    /// Restricts the principals allowed to authenticate. If the policy was loaded from a file,
+    /// it is reloaded whenever the file changes.
+    #[deprecated(note = "use `GssapiFairingBuilder::policy` instead")]
+    pub fn set_policy(&mut self, policy: PrincipalPolicy) {
+        self.policy = Some(Arc::new(policy));
+    }
+
+    /// Sets how often the keytab is checked for changes, reloading the credentials when it is
+    /// replaced, e.g. after a key rotation. Set to zero to disable. Defaults to 30 seconds.
+    #[deprecated(note = "use `GssapiFairingBuilder::keytab_poll_interval` instead")]
+    pub fn set_keytab_poll_interval(&mut self, interval: Duration) {
+        self.keytab_poll_interval = interval;
+    }
+
+    /// Sets how often a policy loaded from a file is checked for changes, reloading it when it
+    /// is replaced. Set to zero to disable. Defaults to 30 seconds.
+    #[deprecated(note = "use `GssapiFairingBuilder::policy_poll_interval` instead")]
+    pub fn set_policy_poll_interval(&mut self, interval: Duration) {
+        self.policy_poll_interval = interval;
+    }
+
+    /// Replaces the default in-memory [`MemoryStore`] used to keep half-finished contexts
+    /// between requests. The configured limits are passed to the store, which may enforce
+    /// them or apply its own policy.
+    #[deprecated(note = "use `GssapiFairingBuilder::store` instead")]
+    pub fn set_store<S: ContextStore + 'static>(&mut self, store: S) {
+        self.contexts = Arc::new(store);
+    }
+
+    /// Sets the flags a context must have been established with, such as
+    /// `GSS_C_MUTUAL_FLAG | GSS_C_INTEG_FLAG`. Other contexts are rejected once complete.
+    #[deprecated(note = "use `GssapiFairingBuilder::required_flags` instead")]
+    pub fn set_required_flags(&mut self, flags: CtxFlags) {
+        self.required_flags = flags;
+    }
+
+    /// Sets the flags a context must not have been established with, such as
+    /// `GSS_C_ANON_FLAG`. Other contexts are rejected once complete.
+    #[deprecated(note = "use `GssapiFairingBuilder::forbidden_flags` instead")]
+    pub fn set_forbidden_flags(&mut self, flags: CtxFlags) {
+        self.forbidden_flags = flags;
+    }
+
+    /// Sets which kinds of tokens are passed to GSSAPI. By default NTLM tokens are rejected
+    /// up front, as MIT Kerberos can't accept them without an extra mechanism.
+    #[deprecated(note = "use `GssapiFairingBuilder::token_policy` instead")]
+    pub fn set_token_policy(&mut self, policy: TokenPolicy) {
+        self.tokens = policy;
+    }
+
+    /// Sets how the [`InGroup`] guard and `#[gssapi_require(group = ...)]` look up groups of
+    /// clients whose ticket has no PAC, or for groups without a SID.
+    ///
+    /// [`InGroup`]: crate::InGroup
+    #[deprecated(note = "use `GssapiFairingBuilder::group_resolver` instead")]
+    pub fn set_group_resolver<R: GroupResolver + 'static>(&mut self, resolver: R) {
+        self.groups = Some(Arc::new(resolver));
+    }
+}
+
+impl GssapiFairing {
+    /// Checks an authenticated client against the policies of the fairing, before it is
+    /// handed to the request guard.
+    fn authorize(&self, auth: &GssapiAuth) -> Result<(), GssapiError> {
+        let flags = auth.flags.unwrap_or(CtxFlags::empty());
+        let missing = self.required_flags.difference(flags);
+        if !missing.is_empty() {
+            let source = auth.source.clone().unwrap_or_default();
+            let missing = ContextFlag::describe(missing);
+            warn!("Kerberos: Rejected context without {} from: {}", missing, source);
+            return Err(GssapiError::MissingFlags(missing));
+        }
+        let forbidden = self.forbidden_flags.intersection(flags);
+        if !forbidden.is_empty() {
+            let source = auth.source.clone().unwrap_or_default();
+            let forbidden = ContextFlag::describe(forbidden);
+            warn!("Kerberos: Rejected context with {} from: {}", forbidden, source);
+            return Err(GssapiError::ForbiddenFlags(forbidden));
+        }
+        if !self.realms.is_empty() {
+            let source = auth.source.clone().unwrap_or_default();
+            let realm = auth.principal.as_ref().and_then(|p| p.realm());
+            if !realm.is_some_and(|r| self.realms.allows(r)) {
+                warn!("Kerberos: Rejected principal from unaccepted realm: {}", source);
+                return Err(GssapiError::RealmRejected(source));
+            }
+        }
+        if let Some(policy) = &self.policy {
+            let source = auth.source.clone().unwrap_or_default();
+            if !policy.allows(&source) {
+                warn!("Kerberos: Rejected principal denied by policy: {}", source);
+                return Err(GssapiError::PolicyDenied(source));
+            }
+        }
+        Ok(())
+    }
+
+    /// Warns about keytab problems that don't prevent acquiring credentials but will fail
+    /// logins: a missing service principal, or only weak keys that KDCs may refuse to use.
+    fn check_keytab(&self) {
+        let path = if let Some(p) = self.creds.keytab_path() {
+            p
+        } else {
+            return;
+        };
+        let keytab = match self.creds.load_keytab() {
+            Ok(Some(k)) => k,
+            Ok(None) => return,
+            Err(e) => {
+                warn!("Kerberos: Failed to inspect keytab {}: {}", path.display(), e);
+                return;
+            }
+        };
+        let principals = match self.creds.principal() {
+            Some(p) => match keytab.principals_matching(&p) {
+                m if m.is_empty() => {
+                    warn!("Kerberos: Keytab {} has no keys for {}", path.display(), p);
+                    return;
+                }
+                m => m,
+            },
+            None => keytab.principals(),
+        };
+        for principal in principals {
+            let keys = keytab.current_keys(&principal);
+            if keys.iter().all(|k| k.is_weak()) {
+                let enctypes: Vec<_> = keys.iter().map(|k| k.enctype_name()).collect();
+                warn!(
+                    "Kerberos: Keytab {} only has weak enctypes for {}: {}",
+                    path.display(),
+                    principal,
+                    enctypes.join(", ")
+                );
+            }
+        }
+    }
+
+    /// Explains a failed context by comparing the client's ticket against the cached keys of
+    /// the keytab, as a kvno or enctype mismatch otherwise only shows up as an opaque GSSAPI
+    /// error. Mismatches are warned about at most once per [`MISMATCH_WARNING_INTERVAL`].
+    fn diagnose(&self, token: &[u8]) {
+        let ap_req = if let Some(a) = ApReq::from_token(token) {
+            a
+        } else {
+            return;
+        };
+        let keytab = if let Some(k) = self.creds.keytab_keys() {
+            k
+        } else {
+            return;
+        };
+        match ap_req.mismatch(&keytab) {
+            Some(m) if self.warn_mismatch() => warn!("Kerberos: Keytab mismatch: {}", m),
+            Some(m) => debug!("Kerberos: Keytab mismatch: {}", m),
+            None => debug!("Kerberos: Keytab has the key of the {}", ap_req),
+        }
+    }
+
+    /// Records a failed step for the guard. The mechanism's error token, e.g. a `KRB-ERROR`
+    /// for clock skew, is returned in the challenge so the client can report or recover from
+    /// the actual error.
+    fn reject_step(&self, req: &Request<'_>, e: AcceptError, client: &str) {
+        self.creds.invalidate_on(&e.error);
+        if let Some(tok) = e.token {
+            debug!("Kerberos: Returning error token, client: {}", client);
+            req.local_cache(|| CachedBuf(tok));
+        }
+        req.local_cache(|| GssapiError::from_step(&e.error));
+    }
+
+    /// Whether a keytab mismatch is due for a warning, as every client with a ticket for the
+    /// old key would otherwise add one.
+    fn warn_mismatch(&self) -> bool {
+        let mut last = match self.mismatch_warned.lock() {
+            Ok(l) => l,
+            Err(_) => return false,
+        };
+        if last.is_some_and(|t| t.elapsed() < MISMATCH_WARNING_INTERVAL) {
+            return false;
+        }
+        *last = Some(Instant::now());
+        true
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -68,10 +449,89 @@ impl Fairing for GssapiFairing {
     fn info(&self) -> Info {
         Info {
             name: "Kerberos Authentication",
-            kind: Kind::Response | Kind::Request,
+            kind: Kind::Response | Kind::Request | Kind::Ignite | Kind::Liftoff,
+        }
+    }
+
+    /// Validates the GSSAPI configuration and acquires the acceptor credentials up front,
+    /// aborting launch with a description of the failing step if the service name, keytab or
+    /// mechanisms are misconfigured, and warning about keytab contents likely to fail logins.
+    /// Also hands the group resolver to the request guards.
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        if let Err(e) = self.creds.validate() {
+            error!("Kerberos: Invalid configuration: {}", e);
+            return Err(rocket);
+        }
+        self.check_keytab();
+        match &self.groups {
+            Some(groups) if rocket.state::<Resolver>().is_none() => {
+                Ok(rocket.manage(Resolver(groups.clone())))
+            }
+            _ => Ok(rocket),
+        }
+    }
+
+    /// Starts background tasks which periodically prune contexts of clients that have
+    /// abandoned their negotiation and watch the keytab and policy file for changes, stopping
+    /// when Rocket shuts down.
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        if !self.keytab_poll_interval.is_zero() {
+            rocket::tokio::spawn(watch_keytab(
+                Arc::downgrade(&self.creds),
+                self.keytab_poll_interval,
+                rocket.shutdown(),
+            ));
+        }
+
+        if let Some((policy, path)) = self
+            .policy
+            .as_ref()
+            .filter(|_| !self.policy_poll_interval.is_zero())
+            .and_then(|p| p.path().map(|path| (Arc::downgrade(p), path.to_path_buf())))
+        {
+            rocket::tokio::spawn(watch_file(
+                path.clone(),
+                self.policy_poll_interval,
+                rocket.shutdown(),
+                move || {
+                    let policy = if let Some(p) = policy.upgrade() {
+                        p
+                    } else {
+                        return false;
+                    };
+                    match policy.reload() {
+                        Ok(()) => info!("Kerberos: Reloaded policy {}", path.display()),
+                        Err(e) => warn!("Kerberos: Keeping old policy: {}", e),
+                    }
+                    true
+                },
+            ));
         }
+
+        let contexts = Arc::downgrade(&self.contexts);
+        let limits = self.limits;
+        let mut interval = rocket::tokio::time::interval(self.sweep_interval);
+        let mut shutdown = rocket.shutdown();
+
+        rocket::tokio::spawn(async move {
+            loop {
+                rocket::tokio::select! {
+                    _ = interval.tick() => {},
+                    _ = &mut shutdown => break,
+                }
+                let contexts = if let Some(c) = contexts.upgrade() {
+                    c
+                } else {
+                    break;
+                };
+                let pruned = contexts.sweep(&limits);
+                if pruned > 0 {
+                    info!("Kerberos: Pruned {} stale contexts", pruned);
+                }
+            }
+        });
     }
-    
+
     /// This function handles the GSSAPI data sent from the client in the `Authorization: Negotiate`
     /// header, parsing it to a format suitable for use for the GssapiAuth request guard.
     ///
@@ -91,112 +551,126 @@ impl Fairing for GssapiFairing {
                 c
             } else {
                 info!("Kerberos: Failed to identify client");
+                req.local_cache(|| GssapiError::Unidentified);
                 return;
             };
 
             if let Ok(client_tok) = &BASE64_STANDARD.decode(token) {
-                let mut ctx_store = if let Ok(c) = self.contexts.lock() {
-                    c
-                } else {
-                    error!("Kerberos: Failed to lock context store");
+                let kind = TokenKind::of(client_tok);
+                let offered = NegToken::parse(client_tok).ok();
+                let described = offered
+                    .as_ref()
+                    .map_or_else(|| format!("{} token", kind), |t| t.to_string());
+                if self.tokens.action(kind) == TokenAction::Reject {
+                    warn!("Kerberos: Rejected {}, client: {}", described, client);
+                    req.local_cache(|| GssapiError::UnsupportedToken(kind));
                     return;
-                };
+                }
+                debug!("Kerberos: Received {}, client: {}", described, client);
 
-                let mut is_complete = false;
-                let mut is_failed = false;
-                let buf = if let Some(ctx) = ctx_store.get(&client.to_string()) {
-                    // Existing context still present
-                    if let Ok(mut ctx) = ctx.try_lock() {
-                        let res = match ctx.step(client_tok) {
-                            Ok(res) => res,
-                            Err(e) => {
-                                warn!(
-                                    "Kerberos: Failed to work context: {}, client: {}",
-                                    e, client
-                                );
-                                is_failed = true;
-                                None
-                            }
-                        };
-
-                        // Pass the Gssapi data to the request guard
-                        if ctx.is_complete() {
-                            req.local_cache(|| {
-                                let g: GssapiAuth = ctx.into();
-                                g
-                            });
-                            is_complete = true;
-                        }
-                        res
-                    } else {
-                        error!("Kerberos: Can't lock context for: {}", client);
-                        is_failed = true;
+                let pending = match self.contexts.take(&client) {
+                    Some(p) if p.is_stale(Instant::now(), &self.limits) => {
+                        // The sweeper hasn't gotten to it yet, start over
+                        info!("Kerberos: Discarding stale context for: {}", client);
                         None
                     }
-                } else {
-                    None
+                    p => p,
                 };
 
-                if is_failed {
-                    ctx_store.remove(&client.to_string());
+                // Continue an existing context. If that fails, the client may have started
+                // over, so an initial token is tried on a new context instead. A NegTokenResp
+                // can't start one, and the error token of the existing context tells why.
+                let continuing = matches!(offered, Some(NegToken::Resp(_)));
+                let stepped = match pending.map(|mut p| (p.ctx.step(client_tok), p)) {
+                    Some((Ok(buf), mut p)) => {
+                        p.last_seen = Instant::now();
+                        Some((p, buf))
+                    }
+                    Some((Err(e), _)) if continuing => {
+                        warn!(
+                            "Kerberos: Failed to continue context: {}, client: {}, token: {}",
+                            e.error, client, described
+                        );
+                        self.reject_step(req, e, &client);
+                        return;
+                    }
+                    Some((Err(e), _)) => {
+                        // Not a warning yet, the new context reports it if it fails as well
+                        debug!(
+                            "Kerberos: Failed to continue context: {}, client: {}, token: {}",
+                            e.error, client, described
+                        );
+                        self.creds.invalidate_on(&e.error);
+                        None
+                    }
+                    None => None,
                 };
 
-                let buf = if buf.is_none() && !is_complete {
+                let (pending, buf) = if let Some(s) = stepped {
+                    s
+                } else {
                     // Initiate a new context
-                    let cred = Cred::acquire(
-                        self.name.as_ref(),
-                        None,
-                        self.usage,
-                        self.desired_mechs.as_ref(),
-                    );
-                    let cred = if let Ok(c) = cred {
-                        c
-                    } else {
-                        error!(
-                            "Kerberos: Failed to acquire credentials: {}",
-                            cred.unwrap_err()
-                        );
-                        return;
+                    let cred = self.creds.get();
+                    let cred = match cred {
+                        Ok(c) => c,
+                        Err(e) => {
+                            error!("Kerberos: Failed to acquire credentials: {}", e);
+                            req.local_cache(|| GssapiError::Credentials(e.to_string()));
+                            return;
+                        }
                     };
 
-                    let mut ctx = ServerCtx::new(Some(cred));
-
-                    let res = match ctx.step(client_tok) {
-                        Ok(res) => res,
+                    let ctx = AcceptCtx::new(Some(cred));
+                    let mut p = PendingContext::new(ctx, req.client_ip());
+                    match p.ctx.step(client_tok) {
+                        Ok(buf) => (p, buf),
                         Err(e) => {
                             warn!(
-                                "Kerberos: Failed to work context: {}, client: {}",
-                                e, client
+                                "Kerberos: Failed to work context: {}, client: {}, token: {}",
+                                e.error, client, described
                             );
+                            self.diagnose(client_tok);
+                            self.reject_step(req, e, &client);
                             return;
                         }
-                    };
+                    }
+                };
 
-                    if !ctx.is_complete() {
-                        // Save the context waiting for the next HTTP request
-                        ctx_store.insert(client.to_string(), Arc::new(Mutex::new(ctx)));
-                    } else {
-                        // Pass the Gssapi data to the request guard
-                        req.local_cache(|| GssapiAuth::from(ctx));
-                    };
-                    res
+                let buf = if pending.ctx.is_complete() {
+                    let auth = GssapiAuth::from(pending.ctx);
+                    match self.authorize(&auth) {
+                        Ok(()) => {
+                            // Pass the Gssapi data to the request guard
+                            req.local_cache(|| auth);
+                            buf
+                        }
+                        Err(e) => {
+                            req.local_cache(|| e);
+                            None
+                        }
+                    }
+                } else if self.contexts.insert(client.clone(), pending, &self.limits) {
+                    // Saved, waiting for the next HTTP request
+                    req.local_cache(|| GssapiError::ContinueNeeded);
+                    buf
                 } else {
+                    warn!(
+                        "Kerberos: Too many concurrent negotiations, shedding context for: {}",
+                        client
+                    );
+                    req.local_cache(|| GssapiError::Overloaded);
                     None
                 };
 
-                if is_complete {
-                    // Clean up the old context
-                    ctx_store.remove(&client.to_string());
-                }
-
                 if let Some(buf) = buf {
-                    req.local_cache(|| CachedBuf(buf.to_vec()));
+                    req.local_cache(|| CachedBuf(buf));
                 }
             } else {
                 warn!(
                     "Kerberos: Failed to decode Negotiate header: {}, client: {}",
                     token, client
                 );
+                req.local_cache(|| GssapiError::Malformed);
             }
         }
     }
@@ -214,14 +688,14 @@ impl Fairing for GssapiFairing {
     /// the final server token.
     async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
         match res.status() {
-            Status::Unauthorized => {
+            Status::Unauthorized if self.response.challenge => {
                 let buf = &req.local_cache(|| CachedBuf(Vec::<u8>::new())).0;
                 res.set_header(Header::new(
                     "WWW-Authenticate",
                     format!("Negotiate {}", BASE64_STANDARD.encode(buf)),
                 ));
             }
-            Status::Ok => {
+            Status::Ok if self.response.final_token => {
                 let buf = &req.local_cache(|| CachedBuf(Vec::<u8>::new())).0;
                 if !buf.is_empty() {
                     res.set_header(Header::new(
@@ -234,3 +708,77 @@ impl Fairing for GssapiFairing {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Identifier, Mechanism, NameType};
+
+    #[test]
+    fn builds_from_default_config() {
+        let fairing = GssapiFairing::from_config(&GssapiConfig::default()).unwrap();
+        assert_eq!(fairing.limits.idle_timeout, Duration::from_secs(30));
+        assert_eq!(fairing.limits.negotiation_timeout, Duration::from_secs(120));
+        assert_eq!(fairing.tokens.ntlm, TokenAction::Reject);
+    }
+
+    #[test]
+    fn builds_from_config() {
+        let config = GssapiConfig {
+            principal: Some("HTTP@www.example.com".into()),
+            name_type: NameType::HostbasedService,
+            mechanisms: vec![Mechanism::Spnego, Mechanism::Kerberos],
+            required_flags: vec![ContextFlag::Mutual],
+            forbidden_flags: vec![ContextFlag::Anonymous],
+            identifier: Identifier::Header("X-Session".into()),
+            keytab_poll_interval: 0,
+            ..GssapiConfig::default()
+        };
+        let fairing = GssapiFairing::from_config(&config).unwrap();
+        assert_eq!(fairing.required_flags, CtxFlags::GSS_C_MUTUAL_FLAG);
+        assert_eq!(fairing.forbidden_flags, CtxFlags::GSS_C_ANON_FLAG);
+        assert_eq!(fairing.keytab_poll_interval, Duration::ZERO);
+    }
+
+    #[test]
+    fn raises_negotiation_timeout_with_context_timeout() {
+        let config = GssapiConfig {
+            context_timeout: 300,
+            ..GssapiConfig::default()
+        };
+        let fairing = GssapiFairing::from_config(&config).unwrap();
+        assert_eq!(fairing.limits.idle_timeout, Duration::from_secs(300));
+        assert_eq!(fairing.limits.negotiation_timeout, Duration::from_secs(300));
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let config = GssapiConfig {
+            required_flags: vec![ContextFlag::Mutual],
+            forbidden_flags: vec![ContextFlag::Mutual],
+            ..GssapiConfig::default()
+        };
+        assert!(matches!(
+            GssapiFairing::from_config(&config),
+            Err(ConfigError::InvalidOption(_))
+        ));
+
+        let config = GssapiConfig {
+            policy: Some(std::env::temp_dir().join("rocket-gssapi-missing-policy.toml")),
+            ..GssapiConfig::default()
+        };
+        assert!(matches!(
+            GssapiFairing::from_config(&config),
+            Err(ConfigError::Policy(_))
+        ));
+
+        let config = GssapiConfig {
+            groups: Some(std::env::temp_dir().join("rocket-gssapi-missing-groups.toml")),
+            ..GssapiConfig::default()
+        };
+        assert!(matches!(
+            GssapiFairing::from_config(&config),
+            Err(ConfigError::Groups(_))
+        ));
+    }
+}
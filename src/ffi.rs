@@ -0,0 +1,58 @@
+//! Bindings for the GSSAPI extensions not wrapped by libgssapi.
+#![allow(non_camel_case_types)]
+
+use libgssapi_sys::{gss_OID, gss_OID_set, gss_buffer_t, gss_cred_id_t, gss_name_t, OM_uint32};
+use std::os::raw::{c_char, c_int};
+
+pub(crate) type gss_cred_usage_t = c_int;
+
+pub(crate) const GSS_C_BOTH: gss_cred_usage_t = 0;
+pub(crate) const GSS_C_INITIATE: gss_cred_usage_t = 1;
+pub(crate) const GSS_C_ACCEPT: gss_cred_usage_t = 2;
+pub(crate) const GSS_C_INDEFINITE: OM_uint32 = 0xffffffff;
+pub(crate) const GSS_S_COMPLETE: OM_uint32 = 0;
+pub(crate) const GSS_S_CONTINUE_NEEDED: OM_uint32 = 1;
+
+#[repr(C)]
+pub(crate) struct gss_key_value_element_desc {
+    pub key: *const c_char,
+    pub value: *const c_char,
+}
+
+#[repr(C)]
+pub(crate) struct gss_key_value_set_desc {
+    pub count: OM_uint32,
+    pub elements: *mut gss_key_value_element_desc,
+}
+
+extern "C" {
+    pub(crate) fn gss_acquire_cred_from(
+        minor_status: *mut OM_uint32,
+        desired_name: gss_name_t,
+        time_req: OM_uint32,
+        desired_mechs: gss_OID_set,
+        cred_usage: gss_cred_usage_t,
+        cred_store: *const gss_key_value_set_desc,
+        output_cred_handle: *mut gss_cred_id_t,
+        actual_mechs: *mut gss_OID_set,
+        time_rec: *mut OM_uint32,
+    ) -> OM_uint32;
+
+    pub(crate) fn gss_get_name_attribute(
+        minor_status: *mut OM_uint32,
+        name: gss_name_t,
+        attr: gss_buffer_t,
+        authenticated: *mut c_int,
+        complete: *mut c_int,
+        value: gss_buffer_t,
+        display_value: gss_buffer_t,
+        more: *mut c_int,
+    ) -> OM_uint32;
+
+    pub(crate) fn gss_localname(
+        minor_status: *mut OM_uint32,
+        name: gss_name_t,
+        mech_type: gss_OID,
+        localname: gss_buffer_t,
+    ) -> OM_uint32;
+}
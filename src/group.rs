@@ -0,0 +1,361 @@
+use crate::error::GssapiError;
+use crate::guard::GssapiAuth;
+use crate::pac::Sid;
+use crate::principal::Principal;
+use rocket::figment::providers::{Format, Toml};
+use rocket::figment::{self, Figment};
+use rocket::request::{FromRequest, Outcome};
+use rocket::{error, info, Request};
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Names a group for the [`InGroup`] guard.
+///
+/// ```rust
+/// use rocket_gssapi::Group;
+///
+/// struct Admins;
+/// impl Group for Admins {
+///     const NAME: &'static str = "admins";
+///     const SID: Option<&'static str> = Some("S-1-5-21-1004336348-1177238915-682003330-512");
+/// }
+/// ```
+pub trait Group: Send + Sync + 'static {
+    /// The name of the group, as passed to the [`GroupResolver`].
+    const NAME: &'static str;
+    /// The SID of the group, matched against the PAC of tickets issued by Active Directory.
+    /// An invalid SID fails the [`InGroup`] guard with `500 Internal Server Error`.
+    const SID: Option<&'static str> = None;
+}
+
+/// Looks up group membership of clients whose ticket has no PAC, e.g. in LDAP.
+#[rocket::async_trait]
+pub trait GroupResolver: Send + Sync {
+    /// Whether `principal` is a member of `group`.
+    async fn is_member(
+        &self,
+        principal: &Principal,
+        group: &str,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>>;
+}
+
+/// Resolves groups from a TOML file listing the members of each group, mainly meant for
+/// testing:
+///
+/// ```toml
+/// admins = ["alice@CORP.EXAMPLE", "bob/admin@CORP.EXAMPLE"]
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FileGroupResolver {
+    groups: HashMap<String, Vec<String>>,
+}
+impl FileGroupResolver {
+    pub fn new(groups: HashMap<String, Vec<String>>) -> FileGroupResolver {
+        FileGroupResolver { groups }
+    }
+
+    /// Loads the groups from a TOML file.
+    pub fn load(path: impl AsRef<Path>) -> Result<FileGroupResolver, GroupFileError> {
+        let groups = Figment::from(Toml::file_exact(path.as_ref()))
+            .extract()
+            .map_err(|e| GroupFileError(Box::new(e)))?;
+        Ok(FileGroupResolver { groups })
+    }
+}
+
+/// A group file could not be read or parsed.
+#[derive(Debug)]
+pub struct GroupFileError(Box<figment::Error>);
+
+impl fmt::Display for GroupFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid group file: {}", self.0)
+    }
+}
+
+impl std::error::Error for GroupFileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&*self.0)
+    }
+}
+
+#[rocket::async_trait]
+impl GroupResolver for FileGroupResolver {
+    async fn is_member(
+        &self,
+        principal: &Principal,
+        group: &str,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(self.groups.get(group).is_some_and(|members| {
+            members
+                .iter()
+                .any(|m| Principal::parse(m).is_ok_and(|m| m.is_same(principal)))
+        }))
+    }
+}
+
+/// The resolver of the fairing, managed so request guards can reach it.
+pub(crate) struct Resolver(pub Arc<dyn GroupResolver>);
+
+/// Whether the client is a member of the group `name`. The PAC is authoritative if the group
+/// has a SID and the ticket has a PAC, otherwise the resolver is asked, if there is one.
+pub(crate) async fn is_member(
+    req: &Request<'_>,
+    auth: &GssapiAuth,
+    name: &str,
+    sid: Option<&Sid>,
+) -> Result<bool, GssapiError> {
+    if let (Some(sid), Some(info)) = (sid, &auth.logon_info) {
+        return Ok(info.is_member_of(sid));
+    }
+    let (resolver, principal) = match (req.rocket().state::<Resolver>(), &auth.principal) {
+        (Some(r), Some(p)) => (r, p),
+        _ => return Ok(false),
+    };
+    resolver.0.is_member(principal, name).await.map_err(|e| {
+        error!("Kerberos: Failed to resolve group {} of {}: {}", name, principal, e);
+        GssapiError::GroupLookup(e.to_string())
+    })
+}
+
+/// A request guard for clients in the group `G`, failing with `403 Forbidden` for other
+/// authenticated clients. Dereferences to the [`GssapiAuth`] of the client.
+///
+/// ```rust,ignore
+/// #[get("/admin")]
+/// async fn admin(admin: InGroup<Admins>) -> String {
+///     format!("Hello {:?}!", admin.source)
+/// }
+/// ```
+pub struct InGroup<G> {
+    pub auth: GssapiAuth,
+    group: PhantomData<fn() -> G>,
+}
+impl<G> Deref for InGroup<G> {
+    type Target = GssapiAuth;
+
+    fn deref(&self) -> &Self::Target {
+        &self.auth
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, G: Group> FromRequest<'r> for InGroup<G> {
+    type Error = GssapiError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let auth = match req.guard::<GssapiAuth>().await {
+            Outcome::Success(a) => a,
+            Outcome::Error(e) => return Outcome::Error(e),
+            Outcome::Forward(s) => return Outcome::Forward(s),
+        };
+
+        let sid = match G::SID.map(str::parse::<Sid>).transpose() {
+            Ok(sid) => sid,
+            Err(_) => {
+                let sid = G::SID.unwrap_or_default();
+                error!("Kerberos: Group {} has an invalid SID {}", G::NAME, sid);
+                let e = GssapiError::GroupLookup(format!("invalid SID {} of {}", sid, G::NAME));
+                return Outcome::Error((e.status(), e));
+            }
+        };
+        match is_member(req, &auth, G::NAME, sid.as_ref()).await {
+            Ok(true) => Outcome::Success(InGroup {
+                auth,
+                group: PhantomData,
+            }),
+            Ok(false) => {
+                let source = auth.source.clone().unwrap_or_default();
+                info!("Kerberos: {} is not a member of {}", source, G::NAME);
+                let e = GssapiError::Forbidden(source);
+                Outcome::Error((e.status(), e))
+            }
+            Err(e) => Outcome::Error((e.status(), e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pac::tests::{encode, Fixture};
+    use crate::pac::LogonInfo;
+    use rocket::fairing::AdHoc;
+    use rocket::http::{Header, Status};
+    use rocket::local::blocking::Client;
+    use rocket::{get, routes};
+    use std::fs;
+
+    const GROUPS: &str = r#"
+        admins = ["alice@CORP.EXAMPLE", "bob/admin@corp.example", "carol"]
+        testers = ["dave@CORP.EXAMPLE"]
+    "#;
+
+    /// The PAC fixture lists Domain Admins, but not Auditors.
+    struct Admins;
+    impl Group for Admins {
+        const NAME: &'static str = "admins";
+        const SID: Option<&'static str> = Some("S-1-5-21-1004336348-1177238915-682003330-512");
+    }
+
+    struct Auditors;
+    impl Group for Auditors {
+        const NAME: &'static str = "auditors";
+        const SID: Option<&'static str> = Some("S-1-5-21-1004336348-1177238915-682003330-599");
+    }
+
+    struct Testers;
+    impl Group for Testers {
+        const NAME: &'static str = "testers";
+    }
+
+    /// A resolver that is down, showing whether it was asked.
+    struct Failing;
+
+    #[rocket::async_trait]
+    impl GroupResolver for Failing {
+        async fn is_member(
+            &self,
+            _: &Principal,
+            _: &str,
+        ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+            Err("directory down".into())
+        }
+    }
+
+    fn principal(name: &str) -> Principal {
+        Principal::parse(name).unwrap()
+    }
+
+    fn resolver() -> FileGroupResolver {
+        let groups = Figment::from(Toml::string(GROUPS)).extract().unwrap();
+        FileGroupResolver::new(groups)
+    }
+
+    #[get("/admins")]
+    fn admins(_group: InGroup<Admins>) {}
+
+    #[get("/auditors")]
+    fn auditors(_group: InGroup<Auditors>) {}
+
+    #[get("/testers")]
+    fn testers(_group: InGroup<Testers>) {}
+
+    /// A client authenticating as the principal of the `X-Principal` header, with a PAC if
+    /// `X-Pac` is sent, like the fairing would.
+    fn client(resolver: Option<Arc<dyn GroupResolver>>) -> Client {
+        let auth = AdHoc::on_request("Auth", |req, _| {
+            Box::pin(async move {
+                let principal = req.headers().get_one("X-Principal").map(principal);
+                let pac = req.headers().contains("X-Pac");
+                req.local_cache(|| GssapiAuth {
+                    source: principal.as_ref().map(|p| p.to_string()),
+                    principal,
+                    logon_info: if pac {
+                        LogonInfo::parse(&encode(&Fixture::default())).ok()
+                    } else {
+                        None
+                    },
+                    complete: true,
+                    ..GssapiAuth::default()
+                });
+            })
+        });
+        let mut rocket = rocket::build()
+            .attach(auth)
+            .mount("/", routes![admins, auditors, testers]);
+        if let Some(r) = resolver {
+            rocket = rocket.manage(Resolver(r));
+        }
+        Client::untracked(rocket).unwrap()
+    }
+
+    fn status(client: &Client, uri: &'static str, principal: &str, pac: bool) -> Status {
+        let mut req = client.get(uri).header(Header::new("X-Principal", principal.to_string()));
+        if pac {
+            req = req.header(Header::new("X-Pac", "1"));
+        }
+        req.dispatch().status()
+    }
+
+    #[rocket::async_test]
+    async fn resolves_members_from_a_file() {
+        let path = std::env::temp_dir()
+            .join(format!("rocket-gssapi-groups-{}.toml", std::process::id()));
+        fs::write(&path, GROUPS).unwrap();
+        let resolver = FileGroupResolver::load(&path);
+        let _ = fs::remove_file(&path);
+        let resolver = resolver.unwrap();
+
+        let is_member = |name: &str, group: &'static str| {
+            let principal = principal(name);
+            let resolver = &resolver;
+            async move { resolver.is_member(&principal, group).await.unwrap() }
+        };
+        assert!(is_member("alice@CORP.EXAMPLE", "admins").await);
+        // Realms are compared case-insensitively, components exactly
+        assert!(is_member("bob/admin@CORP.EXAMPLE", "admins").await);
+        assert!(!is_member("bob@CORP.EXAMPLE", "admins").await);
+        assert!(!is_member("Alice@CORP.EXAMPLE", "admins").await);
+        // Either side without a realm never matches
+        assert!(!is_member("carol@CORP.EXAMPLE", "admins").await);
+        assert!(!is_member("alice", "admins").await);
+        assert!(!is_member("alice@CORP.EXAMPLE", "testers").await);
+        assert!(!is_member("alice@CORP.EXAMPLE", "nobody").await);
+    }
+
+    #[test]
+    fn rejects_invalid_group_files() {
+        let path = std::env::temp_dir()
+            .join(format!("rocket-gssapi-groups-invalid-{}.toml", std::process::id()));
+        fs::write(&path, "admins = \"alice@CORP.EXAMPLE\"").unwrap();
+        let invalid = FileGroupResolver::load(&path);
+        let _ = fs::remove_file(&path);
+        assert!(invalid.unwrap_err().to_string().starts_with("invalid group file: "));
+        assert!(FileGroupResolver::load(&path).is_err());
+    }
+
+    #[test]
+    fn pac_is_authoritative_for_groups_with_a_sid() {
+        let client = client(Some(Arc::new(Failing)));
+        assert_eq!(status(&client, "/admins", "mallory@CORP.EXAMPLE", true), Status::Ok);
+        assert_eq!(
+            status(&client, "/auditors", "alice@CORP.EXAMPLE", true),
+            Status::Forbidden
+        );
+        // Without a PAC the resolver is asked, and fails
+        assert_eq!(
+            status(&client, "/admins", "alice@CORP.EXAMPLE", false),
+            Status::InternalServerError
+        );
+    }
+
+    #[test]
+    fn asks_the_resolver_without_a_pac_or_sid() {
+        let client = client(Some(Arc::new(resolver())));
+        assert_eq!(status(&client, "/admins", "alice@CORP.EXAMPLE", false), Status::Ok);
+        assert_eq!(
+            status(&client, "/admins", "mallory@CORP.EXAMPLE", false),
+            Status::Forbidden
+        );
+        assert_eq!(status(&client, "/testers", "dave@CORP.EXAMPLE", true), Status::Ok);
+        assert_eq!(
+            status(&client, "/testers", "alice@CORP.EXAMPLE", true),
+            Status::Forbidden
+        );
+    }
+
+    #[test]
+    fn denies_without_a_resolver() {
+        let client = client(None);
+        assert_eq!(
+            status(&client, "/testers", "dave@CORP.EXAMPLE", false),
+            Status::Forbidden
+        );
+        assert_eq!(status(&client, "/admins", "mallory@CORP.EXAMPLE", true), Status::Ok);
+    }
+}
@@ -1,10 +1,18 @@
-use libgssapi::context::{SecurityContext, ServerCtx};
+use libgssapi::context::{CtxFlags, SecurityContext, ServerCtx};
+use crate::accept::AcceptCtx;
+use crate::error::GssapiError;
+use crate::local::local_name;
+use crate::pac::{LogonInfo, Sid};
+use crate::principal::Principal;
 use rocket::Request;
+use std::fmt;
 use std::ops::Deref;
 use std::sync::{Arc, MutexGuard};
+use std::time::{Duration, SystemTime};
 use libgssapi::credential::Cred;
+use libgssapi::name::Name;
+use libgssapi::oid::Oid;
 use rocket::form::Shareable;
-use rocket::http::Status;
 use rocket::request::{FromRequest, Outcome};
 use rocket::tokio::sync::Mutex;
 
@@ -12,7 +20,22 @@ use rocket::tokio::sync::Mutex;
 pub struct GssapiAuth {
     pub target: Option<String>,
     pub source: Option<String>,
+    /// The parsed `source`, the principal of the authenticated client.
+    pub principal: Option<Principal>,
+    /// The logon information from the PAC of tickets issued by Active Directory, with the
+    /// client's SIDs and groups.
+    pub logon_info: Option<LogonInfo>,
+    /// The authenticated name of the client, kept for [`GssapiAuth::local_name()`].
+    pub(crate) source_name: Option<SourceName>,
+    /// Seconds the context was valid for when it was established, see `expires`.
     pub lifetime: Option<f32>,
+    /// When the context, and thereby the client's ticket, expires.
+    pub expires: Option<SystemTime>,
+    /// The negotiated mechanism, e.g. `GSS_MECH_KRB5` even if the client used SPNEGO.
+    pub mechanism: Option<&'static Oid>,
+    /// The flags returned when the context was established, which tell the services actually
+    /// provided rather than the ones requested by the client.
+    pub flags: Option<CtxFlags>,
     pub complete: bool,
     pub delegated_cred: Arc<Mutex<Option<Cred>>>,
 }
@@ -24,49 +47,317 @@ impl Deref for GssapiAuth {
     }
 }
 
+/// A shared [`Name`], so that `GssapiAuth` stays cheap to clone.
+#[derive(Clone)]
+pub(crate) struct SourceName(Arc<Name>);
+impl fmt::Debug for SourceName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0.to_string())
+    }
+}
+
 unsafe impl Shareable for GssapiAuth {
     fn size(&self) -> usize {
         size_of_val(self)
     }
 }
-impl From<ServerCtx> for GssapiAuth {
+impl GssapiAuth {
+    /// The local account the principal maps to, according to the `auth_to_local` rules of
+    /// `krb5.conf`. Mapped with each call, see [`LocalUser`](crate::LocalUser) to resolve it.
+    pub fn local_name(&self) -> Option<String> {
+        self.source_name.as_ref().and_then(|n| local_name(&n.0))
+    }
 
-    fn from(mut ctx: ServerCtx) -> GssapiAuth {
+    /// The SID of the client's account, for tickets issued by Active Directory.
+    pub fn user_sid(&self) -> Option<&Sid> {
+        self.logon_info.as_ref().map(LogonInfo::user_sid)
+    }
+
+    /// The SIDs of the client's groups, or none if the ticket had no PAC.
+    pub fn group_sids(&self) -> &[Sid] {
+        self.logon_info.as_ref().map_or(&[], LogonInfo::group_sids)
+    }
+
+    /// The NetBIOS name of the client's domain, for tickets issued by Active Directory.
+    pub fn logon_domain(&self) -> Option<&str> {
+        self.logon_info.as_ref().map(LogonInfo::logon_domain)
+    }
+
+    fn has_flag(&self, flag: CtxFlags) -> bool {
+        self.flags.is_some_and(|f| f.contains(flag))
+    }
+
+    /// Whether the server was authenticated to the client as well.
+    pub fn is_mutual(&self) -> bool {
+        self.has_flag(CtxFlags::GSS_C_MUTUAL_FLAG)
+    }
+
+    /// Whether the client delegated its credentials, available in `delegated_cred`.
+    pub fn is_delegated(&self) -> bool {
+        self.has_flag(CtxFlags::GSS_C_DELEG_FLAG)
+    }
+
+    /// Whether messages of the context can be integrity protected.
+    pub fn has_integrity(&self) -> bool {
+        self.has_flag(CtxFlags::GSS_C_INTEG_FLAG)
+    }
+
+    /// Whether messages of the context can be encrypted.
+    pub fn has_confidentiality(&self) -> bool {
+        self.has_flag(CtxFlags::GSS_C_CONF_FLAG)
+    }
+
+    /// Whether the client authenticated anonymously, in which case `source` names the
+    /// anonymous principal.
+    pub fn is_anonymous(&self) -> bool {
+        self.has_flag(CtxFlags::GSS_C_ANON_FLAG)
+    }
+
+    fn from_ctx(ctx: &mut ServerCtx) -> GssapiAuth {
+        let mut auth = GssapiAuth::from_names(ctx.source_name().ok(), ctx.target_name().ok());
+        auth.set_lifetime(ctx.lifetime().ok());
+        auth.mechanism = ctx.mechanism().ok();
+        auth.flags = ctx.flags().ok();
+        auth.complete = ctx.is_complete();
+        auth.delegated_cred = Arc::new(Mutex::new(ctx.take_delegated_cred()));
+        auth
+    }
+
+    /// Sets `lifetime` to the remaining lifetime of a context, and `expires` to when it ends.
+    fn set_lifetime(&mut self, lifetime: Option<Duration>) {
+        self.lifetime = lifetime.map(|l| l.as_secs_f32());
+        self.expires = lifetime.and_then(|l| SystemTime::now().checked_add(l));
+    }
+
+    /// Fills in what is known from the names of a context, including what the PAC and
+    /// `auth_to_local` rules tell about the client.
+    fn from_names(source_name: Option<Name>, target_name: Option<Name>) -> GssapiAuth {
+        let source = source_name.as_ref().map(|t| t.to_string());
         GssapiAuth {
-            target: ctx.target_name()
-                .map_or(None, |t| Some(t.to_string())),
-            source: ctx.source_name()
-                .map_or(None, |t| Some(t.to_string())),
-            lifetime: ctx.lifetime().map_or(None, |l| Some(l.as_secs_f32())),
-            complete: ctx.is_complete(),
-            delegated_cred: Arc::new(Mutex::new(ctx.take_delegated_cred()))
+            target: target_name.map(|t| t.to_string()),
+            principal: source.as_deref().and_then(|s| Principal::parse(s).ok()),
+            source,
+            logon_info: source_name.as_ref().and_then(LogonInfo::of),
+            source_name: source_name.map(|n| SourceName(Arc::new(n))),
+            ..GssapiAuth::default()
         }
     }
 }
+impl From<ServerCtx> for GssapiAuth {
+
+    fn from(mut ctx: ServerCtx) -> GssapiAuth {
+        GssapiAuth::from_ctx(&mut ctx)
+    }
+}
 impl From<MutexGuard<'_, ServerCtx>> for GssapiAuth {
 
     fn from(mut ctx: MutexGuard<ServerCtx>) -> GssapiAuth {
-        GssapiAuth {
-            target: ctx.target_name()
-                .map_or(None, |t| Some(t.to_string())),
-            source: ctx.source_name()
-                .map_or(None, |t| Some(t.to_string())),
-            lifetime: ctx.lifetime().map_or(None, |l| Some(l.as_secs_f32())),
-            complete: ctx.is_complete(),
-            delegated_cred: Arc::new(Mutex::new(ctx.take_delegated_cred()))
-        }
+        GssapiAuth::from_ctx(&mut ctx)
+    }
+}
+impl From<AcceptCtx> for GssapiAuth {
+    fn from(mut ctx: AcceptCtx) -> GssapiAuth {
+        let mut auth = GssapiAuth::from_names(ctx.take_source_name(), ctx.target_name());
+        auth.set_lifetime(ctx.lifetime());
+        auth.mechanism = ctx.mechanism();
+        auth.flags = Some(ctx.flags());
+        auth.complete = ctx.is_complete();
+        auth.delegated_cred = Arc::new(Mutex::new(ctx.take_delegated_cred()));
+        auth
     }
 }
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for GssapiAuth {
-    type Error = std::convert::Infallible;
+    type Error = GssapiError;
 
+    /// Fails with the reason the fairing recorded, including [`GssapiError::NoHeader`] and
+    /// [`GssapiError::ContinueNeeded`] for clients that haven't authenticated yet, and the
+    /// status of [`GssapiError::status()`]. Take a `Result<GssapiAuth, GssapiError>` to handle
+    /// the failure in the route instead, or an `Option<GssapiAuth>` to serve anonymous clients.
     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
         let g: &GssapiAuth = req.local_cache(GssapiAuth::default);
         if g.complete {
             Outcome::Success(g.clone())
         } else {
-            Outcome::Forward(Status::Unauthorized)
+            let e = req.local_cache(|| GssapiError::NoHeader);
+            Outcome::Error((e.status(), e.clone()))
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::fairing::AdHoc;
+    use rocket::http::{Header, Status};
+    use rocket::local::blocking::{Client, LocalResponse};
+    use rocket::{get, routes};
+
+    /// Failures the fairing may record, selected by index with the `X-Outcome` header.
+    fn errors() -> Vec<GssapiError> {
+        vec![
+            GssapiError::ContinueNeeded,
+            GssapiError::ClockSkew,
+            GssapiError::InvalidToken("bad token".into()),
+            GssapiError::PolicyDenied("mallory@CORP".into()),
+            GssapiError::Credentials("no keytab".into()),
+            GssapiError::GroupLookup("directory down".into()),
+        ]
+    }
+
+    #[get("/result")]
+    fn result(auth: Result<GssapiAuth, GssapiError>) -> String {
+        match auth {
+            Ok(auth) => format!("Ok({})", auth.source.unwrap_or_default()),
+            Err(e) => format!("Err({:?})", e),
+        }
+    }
+
+    #[get("/option")]
+    fn option(auth: Option<GssapiAuth>) -> &'static str {
+        if auth.is_some() {
+            "Some"
+        } else {
+            "None"
+        }
+    }
+
+    #[get("/required")]
+    fn required(_auth: GssapiAuth) {}
+
+    /// A client of a Rocket recording outcomes like the fairing would, without Kerberos.
+    fn client() -> Client {
+        let outcome = AdHoc::on_request("Outcome", |req, _| {
+            Box::pin(async move {
+                match req.headers().get_one("X-Outcome") {
+                    Some("success") => {
+                        req.local_cache(|| GssapiAuth {
+                            source: Some("alice@CORP".into()),
+                            complete: true,
+                            ..GssapiAuth::default()
+                        });
+                    }
+                    Some(i) => {
+                        let e = errors()[i.parse::<usize>().unwrap()].clone();
+                        req.local_cache(|| e);
+                    }
+                    None => {}
+                }
+            })
+        });
+        let rocket = rocket::build()
+            .attach(outcome)
+            .mount("/", routes![result, option, required]);
+        Client::untracked(rocket).unwrap()
+    }
+
+    fn get<'c>(client: &'c Client, uri: &'static str, outcome: Option<&str>) -> LocalResponse<'c> {
+        let mut req = client.get(uri);
+        if let Some(o) = outcome {
+            req = req.header(Header::new("X-Outcome", o.to_string()));
+        }
+        req.dispatch()
+    }
+
+    #[test]
+    fn routes_see_clients_without_a_header() {
+        let client = client();
+        let body = get(&client, "/result", None).into_string();
+        assert_eq!(body.as_deref(), Some("Err(NoHeader)"));
+        assert_eq!(get(&client, "/option", None).into_string().as_deref(), Some("None"));
+        assert_eq!(get(&client, "/required", None).status(), Status::Unauthorized);
+    }
+
+    #[test]
+    fn routes_see_every_failure() {
+        let client = client();
+        for (i, e) in errors().into_iter().enumerate() {
+            let i = i.to_string();
+            let body = get(&client, "/result", Some(&i)).into_string();
+            assert_eq!(body, Some(format!("Err({:?})", e)));
+            let body = get(&client, "/option", Some(&i)).into_string();
+            assert_eq!(body.as_deref(), Some("None"));
+            assert_eq!(get(&client, "/required", Some(&i)).status(), e.status());
+        }
+    }
+
+    #[test]
+    fn routes_see_authenticated_clients() {
+        let client = client();
+        let body = get(&client, "/result", Some("success")).into_string();
+        assert_eq!(body.as_deref(), Some("Ok(alice@CORP)"));
+        let body = get(&client, "/option", Some("success")).into_string();
+        assert_eq!(body.as_deref(), Some("Some"));
+        assert_eq!(get(&client, "/required", Some("success")).status(), Status::Ok);
+    }
+
+    #[test]
+    fn fails_with_the_status_of_the_error() {
+        assert_eq!(GssapiError::NoHeader.status(), Status::Unauthorized);
+        assert_eq!(GssapiError::ContinueNeeded.status(), Status::Unauthorized);
+        assert_eq!(GssapiError::ClockSkew.status(), Status::Unauthorized);
+        let forbidden = GssapiError::Forbidden("alice@CORP".into());
+        assert_eq!(forbidden.status(), Status::Forbidden);
+        let no_account = GssapiError::NoLocalAccount("alice@CORP".into());
+        assert_eq!(no_account.status(), Status::Forbidden);
+        let lookup = GssapiError::AccountLookup("nss down".into());
+        assert_eq!(lookup.status(), Status::InternalServerError);
+        let creds = GssapiError::Credentials("no keytab".into());
+        assert_eq!(creds.status(), Status::InternalServerError);
+    }
+
+    #[test]
+    fn anonymous_clients_have_no_local_name() {
+        assert_eq!(GssapiAuth::default().local_name(), None);
+    }
+
+    fn with_flags(flags: CtxFlags) -> GssapiAuth {
+        GssapiAuth {
+            flags: Some(flags),
+            ..GssapiAuth::default()
+        }
+    }
+
+    #[test]
+    fn tells_the_services_of_the_context() {
+        let auth = with_flags(CtxFlags::GSS_C_MUTUAL_FLAG | CtxFlags::GSS_C_INTEG_FLAG);
+        assert!(auth.is_mutual() && auth.has_integrity());
+        assert!(!auth.is_delegated() && !auth.has_confidentiality() && !auth.is_anonymous());
+
+        let auth = with_flags(
+            CtxFlags::GSS_C_DELEG_FLAG | CtxFlags::GSS_C_CONF_FLAG | CtxFlags::GSS_C_ANON_FLAG,
+        );
+        assert!(auth.is_delegated() && auth.has_confidentiality() && auth.is_anonymous());
+        assert!(!auth.is_mutual() && !auth.has_integrity());
+    }
+
+    #[test]
+    fn provides_no_services_without_flags() {
+        let auth = GssapiAuth::default();
+        assert!(!auth.is_mutual() && !auth.is_delegated() && !auth.is_anonymous());
+        assert!(!auth.has_integrity() && !auth.has_confidentiality());
+        assert!(!with_flags(CtxFlags::empty()).is_mutual());
+    }
+
+    #[test]
+    fn expires_after_the_lifetime() {
+        let mut auth = GssapiAuth::default();
+        let lifetime = Duration::from_secs(600);
+        let before = SystemTime::now();
+        auth.set_lifetime(Some(lifetime));
+        let after = SystemTime::now();
+        assert_eq!(auth.lifetime, Some(600.0));
+        let expires = auth.expires.unwrap();
+        assert!(expires >= before + lifetime && expires <= after + lifetime);
+
+        auth.set_lifetime(None);
+        assert_eq!((auth.lifetime, auth.expires), (None, None));
+    }
+
+    #[test]
+    fn takes_an_incomplete_context() {
+        let auth = GssapiAuth::from(AcceptCtx::new(None));
+        assert!(!auth.complete);
+        assert_eq!((auth.source, auth.target, auth.expires), (None, None, None));
+        assert_eq!(auth.flags, Some(CtxFlags::empty()));
+    }
+}
@@ -0,0 +1,622 @@
+//! Reader for the MIT keytab file format.
+use crate::krb5::{enctype_name, is_weak_enctype};
+use crate::principal::Principal;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{fmt, fs, io};
+
+/// A single key in a keytab.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeytabEntry {
+    pub components: Vec<String>,
+    pub realm: String,
+    /// The name type of the principal, only recorded by version 2 keytabs.
+    pub name_type: Option<u32>,
+    /// When the key was written to the keytab.
+    pub timestamp: SystemTime,
+    pub kvno: u32,
+    pub enctype: i32,
+}
+impl KeytabEntry {
+    /// The principal in its display form, e.g. `HTTP/www.example.com@EXAMPLE.COM`.
+    pub fn principal(&self) -> String {
+        format!("{}@{}", self.components.join("/"), self.realm)
+    }
+
+    /// The name of the encryption type, e.g. `aes256-cts-hmac-sha1-96`.
+    pub fn enctype_name(&self) -> String {
+        enctype_name(self.enctype)
+    }
+
+    /// Whether the encryption type is considered weak, i.e. DES, 3DES or RC4.
+    pub fn is_weak(&self) -> bool {
+        is_weak_enctype(self.enctype)
+    }
+}
+
+/// The keys of a keytab file, read without going through the Kerberos library.
+#[derive(Debug, Clone, Default)]
+pub struct Keytab {
+    pub entries: Vec<KeytabEntry>,
+}
+
+#[derive(Debug)]
+pub enum KeytabError {
+    Io(io::Error),
+    /// The file doesn't start with a known keytab version.
+    BadVersion,
+    /// An entry runs past the end of the file or its record.
+    Truncated,
+}
+impl fmt::Display for KeytabError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeytabError::Io(e) => write!(f, "failed to read keytab: {}", e),
+            KeytabError::BadVersion => write!(f, "not a keytab file"),
+            KeytabError::Truncated => write!(f, "keytab is truncated"),
+        }
+    }
+}
+impl std::error::Error for KeytabError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            KeytabError::Io(e) => Some(e),
+            KeytabError::BadVersion | KeytabError::Truncated => None,
+        }
+    }
+}
+impl From<io::Error> for KeytabError {
+    fn from(e: io::Error) -> Self {
+        KeytabError::Io(e)
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    big_endian: bool,
+}
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], KeytabError> {
+        if self.buf.len() < n {
+            return Err(KeytabError::Truncated);
+        }
+        let (b, rest) = self.buf.split_at(n);
+        self.buf = rest;
+        Ok(b)
+    }
+
+    fn u8(&mut self) -> Result<u8, KeytabError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, KeytabError> {
+        let b = self.bytes(2)?.try_into().unwrap_or_default();
+        Ok(if self.big_endian {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        })
+    }
+
+    fn u32(&mut self) -> Result<u32, KeytabError> {
+        let b = self.bytes(4)?.try_into().unwrap_or_default();
+        Ok(if self.big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        })
+    }
+
+    fn string(&mut self) -> Result<String, KeytabError> {
+        let len = self.u16()? as usize;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+}
+
+impl Keytab {
+    pub fn read(path: &Path) -> Result<Keytab, KeytabError> {
+        Keytab::parse(&fs::read(path)?)
+    }
+
+    /// Parses a version 1 or 2 keytab. Version 1 uses native byte order and counts the realm
+    /// as a component, version 2 is big-endian and records the name type.
+    pub fn parse(data: &[u8]) -> Result<Keytab, KeytabError> {
+        let version = match data {
+            [5, 1, ..] => 1,
+            [5, 2, ..] => 2,
+            _ => return Err(KeytabError::BadVersion),
+        };
+        let mut file = Reader {
+            buf: &data[2..],
+            big_endian: version == 2 || cfg!(target_endian = "big"),
+        };
+
+        let mut entries = Vec::new();
+        while file.buf.len() >= 4 {
+            let size = file.u32()? as i32;
+            if size == 0 {
+                break;
+            }
+            let record = file.bytes(size.unsigned_abs() as usize)?;
+            if size < 0 {
+                // A hole left by a deleted entry
+                continue;
+            }
+
+            let mut r = Reader {
+                buf: record,
+                big_endian: file.big_endian,
+            };
+            let mut count = r.u16()? as usize;
+            if version == 1 {
+                count = count.saturating_sub(1);
+            }
+            let realm = r.string()?;
+            let components = (0..count)
+                .map(|_| r.string())
+                .collect::<Result<Vec<_>, _>>()?;
+            let name_type = if version == 2 { Some(r.u32()?) } else { None };
+            let timestamp = UNIX_EPOCH + Duration::from_secs(r.u32()? as u64);
+            let kvno8 = r.u8()?;
+            let enctype = r.u16()? as i16 as i32;
+            let key_len = r.u16()? as usize;
+            r.bytes(key_len)?;
+            // Newer keytabs append the full 32-bit kvno, which takes precedence if non-zero
+            let kvno = match r.u32() {
+                Ok(kvno) if kvno != 0 => kvno,
+                _ => kvno8 as u32,
+            };
+
+            entries.push(KeytabEntry {
+                components,
+                realm,
+                name_type,
+                timestamp,
+                kvno,
+                enctype,
+            });
+        }
+        Ok(Keytab { entries })
+    }
+
+    /// The distinct principals with keys in the keytab, in order of appearance.
+    pub fn principals(&self) -> Vec<String> {
+        let mut principals: Vec<String> = Vec::new();
+        for p in self.entries.iter().map(KeytabEntry::principal) {
+            if !principals.contains(&p) {
+                principals.push(p);
+            }
+        }
+        principals
+    }
+
+    /// The principals in the keytab matching the service principal `name`. Canonicalizing a
+    /// host-based name without a `domain_realm` mapping leaves its realm empty for the KDC to
+    /// pick, so only the components are compared then.
+    pub fn principals_matching(&self, name: &str) -> Vec<String> {
+        let wanted = match Principal::parse(name) {
+            Ok(p) => p,
+            Err(_) => return Vec::new(),
+        };
+        let mut principals: Vec<String> = Vec::new();
+        for entry in &self.entries {
+            let matches = match wanted.realm() {
+                Some(realm) if !realm.is_empty() => entry.realm == realm,
+                _ => true,
+            };
+            let p = entry.principal();
+            if matches && entry.components == wanted.components() && !principals.contains(&p) {
+                principals.push(p);
+            }
+        }
+        principals
+    }
+
+    /// The keys of `principal` with the highest kvno, which new tickets are encrypted with.
+    pub fn current_keys(&self, principal: &str) -> Vec<&KeytabEntry> {
+        let kvno = self.max_kvno(Some(principal));
+        self.entries
+            .iter()
+            .filter(|e| Some(e.kvno) == kvno && e.principal() == principal)
+            .collect()
+    }
+
+    /// The highest kvno of `principal`, or of any key if no principal is given.
+    pub fn max_kvno(&self, principal: Option<&str>) -> Option<u32> {
+        self.entries
+            .iter()
+            .filter(|e| principal.is_none_or(|p| e.principal() == p))
+            .map(|e| e.kvno)
+            .max()
+    }
+
+    /// Resolves the keytab used for acceptor credentials like MIT Kerberos does: the explicitly
+    /// configured one, else `KRB5_KTNAME`, else `default_keytab_name` of krb5.conf, else the
+    /// built-in default. Only `FILE:` keytabs can be resolved to a path.
+    pub fn resolve_path(configured: Option<&Path>) -> Option<PathBuf> {
+        if let Some(path) = configured {
+            return Some(path.to_path_buf());
+        }
+        let name = std::env::var("KRB5_KTNAME").ok().or_else(|| {
+            let paths =
+                std::env::var("KRB5_CONFIG").unwrap_or_else(|_| "/etc/krb5.conf".to_string());
+            default_keytab_name(&paths)
+        });
+        match name {
+            Some(name) => file_path(&name),
+            None => Some(PathBuf::from("/etc/krb5.keytab")),
+        }
+    }
+}
+
+/// The path of a keytab name such as `FILE:/etc/krb5.keytab`, if it names a file. Names with
+/// `%{...}` tokens aren't expanded, so they are treated as unknown rather than guessed at.
+fn file_path(name: &str) -> Option<PathBuf> {
+    let path = name
+        .strip_prefix("FILE:")
+        .or_else(|| name.strip_prefix("WRFILE:"))
+        .unwrap_or(name);
+    if (path.contains(':') && !path.starts_with('/')) || path.contains("%{") {
+        None
+    } else {
+        Some(PathBuf::from(path))
+    }
+}
+
+/// The `default_keytab_name` of the first file of the colon-separated krb5.conf list that
+/// sets it. Unreadable files are skipped, like MIT Kerberos does.
+fn default_keytab_name(paths: &str) -> Option<String> {
+    paths
+        .split(':')
+        .filter(|p| !p.is_empty())
+        .filter_map(|p| fs::read_to_string(p).ok())
+        .find_map(|conf| libdefault(&conf, "default_keytab_name").map(str::to_string))
+}
+
+/// The value of `key` in the `[libdefaults]` section of a krb5.conf.
+fn libdefault<'a>(conf: &'a str, key: &str) -> Option<&'a str> {
+    let mut libdefaults = false;
+    for line in conf.lines().map(str::trim) {
+        if let Some(section) = line.strip_prefix('[') {
+            libdefaults = section.trim_end_matches(']').trim() == "libdefaults";
+        } else if let Some(value) = line.strip_prefix(key) {
+            match value.trim_start().strip_prefix('=') {
+                Some(value) if libdefaults => return Some(value.trim()),
+                _ => {}
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A keytab entry as written by `ktutil` or `kadmin`.
+    pub(crate) struct Entry {
+        principal: &'static [&'static str],
+        kvno: u32,
+        enctype: u16,
+        /// Whether the 32-bit kvno is appended to the record.
+        kvno32: bool,
+    }
+
+    impl Entry {
+        pub(crate) fn new(principal: &'static [&'static str], kvno: u32, enctype: u16) -> Entry {
+            Entry {
+                principal,
+                kvno,
+                enctype,
+                kvno32: true,
+            }
+        }
+    }
+
+    /// Encodes a keytab of version `version`, in big-endian byte order unless `little_endian`.
+    pub(crate) fn encode(version: u8, little_endian: bool, entries: &[Entry]) -> Vec<u8> {
+        let u16 = |v: u16| {
+            if little_endian {
+                v.to_le_bytes()
+            } else {
+                v.to_be_bytes()
+            }
+        };
+        let u32 = |v: u32| {
+            if little_endian {
+                v.to_le_bytes()
+            } else {
+                v.to_be_bytes()
+            }
+        };
+        let string = |s: &str| [&u16(s.len() as u16)[..], s.as_bytes()].concat();
+
+        let mut data = vec![5, version];
+        for e in entries {
+            let (realm, components) = e.principal.split_last().unwrap();
+            let mut count = components.len() as u16;
+            if version == 1 {
+                count += 1;
+            }
+            let mut record = u16(count).to_vec();
+            record.extend(string(realm));
+            for c in components {
+                record.extend(string(c));
+            }
+            if version == 2 {
+                record.extend(u32(1));
+            }
+            record.extend(u32(1_700_000_000));
+            record.push(e.kvno as u8);
+            record.extend(u16(e.enctype));
+            record.extend(string("0123456789abcdef"));
+            if e.kvno32 {
+                record.extend(u32(e.kvno));
+            }
+            data.extend(u32(record.len() as u32));
+            data.extend(record);
+        }
+        data
+    }
+
+    #[test]
+    fn parses_version_2() {
+        let data = encode(
+            2,
+            false,
+            &[
+                Entry::new(&["HTTP", "web.corp.example", "CORP.EXAMPLE"], 3, 18),
+                Entry::new(&["host", "web.corp.example", "CORP.EXAMPLE"], 2, 17),
+            ],
+        );
+        let keytab = Keytab::parse(&data).unwrap();
+        assert_eq!(
+            keytab.entries[0],
+            KeytabEntry {
+                components: vec!["HTTP".to_string(), "web.corp.example".to_string()],
+                realm: "CORP.EXAMPLE".to_string(),
+                name_type: Some(1),
+                timestamp: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+                kvno: 3,
+                enctype: 18,
+            }
+        );
+        assert_eq!(
+            keytab.principals(),
+            [
+                "HTTP/web.corp.example@CORP.EXAMPLE",
+                "host/web.corp.example@CORP.EXAMPLE"
+            ]
+        );
+        assert_eq!(keytab.entries[1].enctype_name(), "aes128-cts-hmac-sha1-96");
+    }
+
+    #[test]
+    fn parses_version_1_in_native_byte_order() {
+        let entries = [Entry {
+            kvno32: false,
+            ..Entry::new(&["HTTP", "web", "CORP"], 4, 23)
+        }];
+        let data = encode(1, cfg!(target_endian = "little"), &entries);
+        let keytab = Keytab::parse(&data).unwrap();
+        let entry = &keytab.entries[0];
+        // The realm is counted as a component, but not returned as one
+        assert_eq!(entry.principal(), "HTTP/web@CORP");
+        assert_eq!(entry.name_type, None);
+        assert_eq!(entry.kvno, 4);
+        assert!(entry.is_weak());
+    }
+
+    #[test]
+    fn skips_deleted_entries() {
+        let mut data = vec![5, 2];
+        // A hole of 40 bytes left by a deleted entry
+        data.extend((-40i32).to_be_bytes());
+        data.extend([0; 40]);
+        data.extend(&encode(2, false, &[Entry::new(&["HTTP", "web", "CORP"], 5, 18)])[2..]);
+        // Unused space at the end of the file
+        data.extend([0; 8]);
+        let keytab = Keytab::parse(&data).unwrap();
+        assert_eq!(keytab.entries.len(), 1);
+        assert_eq!(keytab.entries[0].kvno, 5);
+    }
+
+    #[test]
+    fn prefers_the_32_bit_kvno() {
+        let data = encode(2, false, &[Entry::new(&["HTTP", "web", "CORP"], 260, 18)]);
+        assert_eq!(Keytab::parse(&data).unwrap().entries[0].kvno, 260);
+
+        // Without the extension, the kvno wraps at 256
+        let entries = [Entry {
+            kvno32: false,
+            ..Entry::new(&["HTTP", "web", "CORP"], 260, 18)
+        }];
+        let data = encode(2, false, &entries);
+        assert_eq!(Keytab::parse(&data).unwrap().entries[0].kvno, 4);
+    }
+
+    #[test]
+    fn rejects_truncated_and_foreign_files() {
+        let data = encode(2, false, &[Entry::new(&["HTTP", "web", "CORP"], 5, 18)]);
+        // Cut off within the record, which the size claims to be longer
+        assert!(matches!(
+            Keytab::parse(&data[..data.len() - 1]),
+            Err(KeytabError::Truncated)
+        ));
+        // A record too short for its contents
+        let mut short = data.clone();
+        let size = u32::from_be_bytes(short[2..6].try_into().unwrap()) - 20;
+        short[2..6].copy_from_slice(&size.to_be_bytes());
+        short.truncate(6 + size as usize);
+        assert!(matches!(Keytab::parse(&short), Err(KeytabError::Truncated)));
+
+        assert!(matches!(
+            Keytab::parse(&[5, 3, 0, 0]),
+            Err(KeytabError::BadVersion)
+        ));
+        assert!(matches!(
+            Keytab::parse(b"\x7fELF"),
+            Err(KeytabError::BadVersion)
+        ));
+        assert!(matches!(Keytab::parse(&[]), Err(KeytabError::BadVersion)));
+        assert!(Keytab::parse(&[5, 2]).unwrap().entries.is_empty());
+    }
+
+    #[test]
+    fn finds_current_keys() {
+        let data = encode(
+            2,
+            false,
+            &[
+                Entry::new(&["HTTP", "web", "CORP"], 6, 18),
+                Entry::new(&["HTTP", "web", "CORP"], 6, 17),
+                Entry::new(&["HTTP", "web", "CORP"], 7, 18),
+                Entry::new(&["HTTP", "web", "CORP"], 7, 17),
+                Entry::new(&["HTTP", "app", "CORP"], 9, 23),
+            ],
+        );
+        let keytab = Keytab::parse(&data).unwrap();
+        assert_eq!(keytab.max_kvno(Some("HTTP/web@CORP")), Some(7));
+        assert_eq!(keytab.max_kvno(None), Some(9));
+        assert_eq!(keytab.max_kvno(Some("HTTP/www@CORP")), None);
+
+        let keys = keytab.current_keys("HTTP/web@CORP");
+        let found: Vec<_> = keys.iter().map(|k| (k.kvno, k.enctype)).collect();
+        assert_eq!(found, [(7, 18), (7, 17)]);
+        assert!(keytab.current_keys("HTTP/www@CORP").is_empty());
+    }
+
+    #[test]
+    fn matches_principals_with_empty_realm() {
+        let data = encode(
+            2,
+            false,
+            &[
+                Entry::new(&["HTTP", "web", "CORP"], 6, 18),
+                Entry::new(&["HTTP", "web", "OTHER"], 2, 18),
+                Entry::new(&["HTTP", "app", "CORP"], 9, 18),
+            ],
+        );
+        let keytab = Keytab::parse(&data).unwrap();
+        assert_eq!(
+            keytab.principals_matching("HTTP/web@CORP"),
+            ["HTTP/web@CORP"]
+        );
+        // The referral realm, as canonicalizing HTTP@web gives without a domain_realm mapping
+        assert_eq!(
+            keytab.principals_matching("HTTP/web@"),
+            ["HTTP/web@CORP", "HTTP/web@OTHER"]
+        );
+        assert_eq!(
+            keytab.principals_matching("HTTP/web"),
+            ["HTTP/web@CORP", "HTTP/web@OTHER"]
+        );
+        assert!(keytab.principals_matching("HTTP/www@").is_empty());
+        assert!(keytab.principals_matching("HTTP/web@NONE").is_empty());
+    }
+
+    #[test]
+    fn resolves_the_configured_path_first() {
+        let path = Path::new("/srv/http.keytab");
+        assert_eq!(Keytab::resolve_path(Some(path)), Some(path.to_path_buf()));
+    }
+
+    #[test]
+    fn resolves_file_keytab_names() {
+        assert_eq!(
+            file_path("FILE:/etc/http.keytab"),
+            Some("/etc/http.keytab".into())
+        );
+        assert_eq!(
+            file_path("WRFILE:/etc/http.keytab"),
+            Some("/etc/http.keytab".into())
+        );
+        assert_eq!(
+            file_path("/etc/http.keytab"),
+            Some("/etc/http.keytab".into())
+        );
+        assert_eq!(file_path("http.keytab"), Some("http.keytab".into()));
+        assert_eq!(file_path("MEMORY:http"), None);
+        assert_eq!(file_path("KEYRING:persistent:0"), None);
+        assert_eq!(file_path("FILE:/etc/%{service}.keytab"), None);
+    }
+
+    #[test]
+    fn reads_libdefaults() {
+        let conf = "[libdefaults]\n  default_realm = CORP\n  default_keytab_name = \
+            FILE:/etc/http.keytab\n[realms]\n  default_keytab_name = /etc/other.keytab\n";
+        assert_eq!(
+            libdefault(conf, "default_keytab_name"),
+            Some("FILE:/etc/http.keytab")
+        );
+        assert_eq!(libdefault(conf, "default_realm"), Some("CORP"));
+        assert_eq!(libdefault(conf, "default_keytab"), None);
+        assert_eq!(
+            libdefault("[realms]\ndefault_realm = CORP\n", "default_realm"),
+            None
+        );
+    }
+
+    #[test]
+    fn reads_default_keytab_name_from_first_file_setting_it() {
+        let dir = std::env::temp_dir();
+        let without = dir.join(format!(
+            "rocket-gssapi-krb5-without-{}.conf",
+            std::process::id()
+        ));
+        let with = dir.join(format!(
+            "rocket-gssapi-krb5-with-{}.conf",
+            std::process::id()
+        ));
+        fs::write(&without, "[libdefaults]\ndefault_realm = CORP\n").unwrap();
+        fs::write(
+            &with,
+            "[libdefaults]\ndefault_keytab_name = /srv/http.keytab\n",
+        )
+        .unwrap();
+
+        let missing = dir.join("rocket-gssapi-missing-krb5.conf");
+        let paths = format!(
+            "{}:{}:{}",
+            missing.display(),
+            without.display(),
+            with.display()
+        );
+        let name = default_keytab_name(&paths);
+        let _ = fs::remove_file(&without);
+        let _ = fs::remove_file(&with);
+        assert_eq!(name.as_deref(), Some("/srv/http.keytab"));
+        assert_eq!(default_keytab_name(&missing.to_string_lossy()), None);
+    }
+
+    #[test]
+    fn falls_back_to_the_environment_and_krb5_conf() {
+        // The only test touching these variables, as tests share the environment
+        let conf = std::env::temp_dir().join(format!(
+            "rocket-gssapi-krb5-env-{}.conf",
+            std::process::id()
+        ));
+        fs::write(
+            &conf,
+            "[libdefaults]\ndefault_keytab_name = FILE:/srv/conf.keytab\n",
+        )
+        .unwrap();
+        std::env::set_var("KRB5_CONFIG", &conf);
+
+        std::env::set_var("KRB5_KTNAME", "FILE:/srv/env.keytab");
+        assert_eq!(Keytab::resolve_path(None), Some("/srv/env.keytab".into()));
+        std::env::set_var("KRB5_KTNAME", "MEMORY:http");
+        assert_eq!(Keytab::resolve_path(None), None);
+
+        std::env::remove_var("KRB5_KTNAME");
+        assert_eq!(Keytab::resolve_path(None), Some("/srv/conf.keytab".into()));
+        std::env::set_var(
+            "KRB5_CONFIG",
+            std::env::temp_dir().join("rocket-gssapi-missing"),
+        );
+        assert_eq!(Keytab::resolve_path(None), Some("/etc/krb5.keytab".into()));
+
+        std::env::remove_var("KRB5_CONFIG");
+        let _ = fs::remove_file(&conf);
+    }
+}
@@ -0,0 +1,350 @@
+//! Decoder for the unencrypted parts of Kerberos AP-REQ messages, for diagnostics.
+use crate::der;
+use crate::keytab::Keytab;
+use crate::spnego::NegToken;
+use crate::token::{KRB5_OID, MS_KRB5_OID};
+use std::fmt;
+
+/// The name of a Kerberos encryption type, e.g. `aes256-cts-hmac-sha1-96` for 18.
+pub(crate) fn enctype_name(enctype: i32) -> String {
+    let name = match enctype {
+        1 => "des-cbc-crc",
+        3 => "des-cbc-md5",
+        16 => "des3-cbc-sha1",
+        17 => "aes128-cts-hmac-sha1-96",
+        18 => "aes256-cts-hmac-sha1-96",
+        19 => "aes128-cts-hmac-sha256-128",
+        20 => "aes256-cts-hmac-sha384-192",
+        23 => "arcfour-hmac",
+        24 => "arcfour-hmac-exp",
+        25 => "camellia128-cts-cmac",
+        26 => "camellia256-cts-cmac",
+        _ => return format!("enctype {}", enctype),
+    };
+    name.to_string()
+}
+
+/// Whether an encryption type is considered weak: single DES, triple DES and RC4.
+pub(crate) fn is_weak_enctype(enctype: i32) -> bool {
+    matches!(enctype, 1..=7 | 16 | 23 | 24)
+}
+
+/// What a client's AP-REQ reveals about the ticket without decrypting it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApReq {
+    /// The realm of the service.
+    pub realm: String,
+    /// The components of the service principal, e.g. `["HTTP", "web.example.com"]`.
+    pub sname: Vec<String>,
+    /// The key version the ticket is encrypted with, if the KDC included it.
+    pub kvno: Option<u32>,
+    /// The encryption type the ticket is encrypted with.
+    pub enctype: i32,
+}
+impl ApReq {
+    /// Finds and decodes the AP-REQ of a client token, which may be a bare AP-REQ, a Kerberos
+    /// token with a GSSAPI header or a SPNEGO token with an optimistic Kerberos token.
+    pub fn from_token(token: &[u8]) -> Option<ApReq> {
+        match der::read(token)? {
+            (t, _) if t.tag == 0x6e => ApReq::parse(token),
+            (t, _) if t.tag == 0x60 => {
+                let (oid, inner) = der::expect(t.value, 0x06)?;
+                if oid == KRB5_OID || oid == MS_KRB5_OID {
+                    // Skip the token ID, 01 00 for an AP-REQ
+                    match inner {
+                        [0x01, 0x00, ap_req @ ..] => ApReq::parse(ap_req),
+                        _ => None,
+                    }
+                } else {
+                    match NegToken::parse(token).ok()? {
+                        NegToken::Init(init) => ApReq::from_token(init.mech_token.as_deref()?),
+                        NegToken::Resp(_) => None,
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Decodes a bare AP-REQ message.
+    pub fn parse(ap_req: &[u8]) -> Option<ApReq> {
+        let (ap_req, _) = der::expect(ap_req, 0x6e)?;
+        let (fields, _) = der::expect(ap_req, 0x30)?;
+        let ticket = field(fields, 0xa3)?;
+        let (ticket, _) = der::expect(ticket, 0x61)?;
+        let (ticket, _) = der::expect(ticket, 0x30)?;
+
+        let (realm, _) = der::expect(field(ticket, 0xa1)?, 0x1b)?;
+        let (sname, _) = der::expect(field(ticket, 0xa2)?, 0x30)?;
+        let (mut names, _) = der::expect(field(sname, 0xa1)?, 0x30)?;
+        let mut components = Vec::new();
+        while !names.is_empty() {
+            let (name, rest) = der::expect(names, 0x1b)?;
+            components.push(String::from_utf8_lossy(name).into_owned());
+            names = rest;
+        }
+
+        let (enc_part, _) = der::expect(field(ticket, 0xa3)?, 0x30)?;
+        let (enctype, _) = der::expect(field(enc_part, 0xa0)?, 0x02)?;
+        let kvno = match field(enc_part, 0xa1) {
+            Some(kvno) => Some(der::integer(der::expect(kvno, 0x02)?.0)? as u32),
+            None => None,
+        };
+
+        Some(ApReq {
+            realm: String::from_utf8_lossy(realm).into_owned(),
+            sname: components,
+            kvno,
+            enctype: der::integer(enctype)? as i32,
+        })
+    }
+
+    /// The service principal in its display form, e.g. `HTTP/web.example.com@EXAMPLE.COM`.
+    pub fn service(&self) -> String {
+        format!("{}@{}", self.sname.join("/"), self.realm)
+    }
+
+    /// Compares the ticket against the keys of a keytab, describing why they don't match,
+    /// or `None` if the keytab has the key the ticket is encrypted with.
+    pub fn mismatch(&self, keytab: &Keytab) -> Option<String> {
+        let service = self.service();
+        let keys: Vec<_> = keytab
+            .entries
+            .iter()
+            .filter(|e| e.principal() == service)
+            .collect();
+        if keys.is_empty() {
+            return Some(format!(
+                "ticket is for {}, which is not in the keytab",
+                service
+            ));
+        }
+
+        let kvno = self
+            .kvno
+            .unwrap_or_else(|| keys.iter().map(|k| k.kvno).max().unwrap_or(0));
+        let keys: Vec<_> = keys.into_iter().filter(|k| k.kvno == kvno).collect();
+        if keys.is_empty() {
+            return Some(format!(
+                "ticket kvno {}, keytab has kvno {} for {}",
+                kvno,
+                keytab.max_kvno(Some(&service)).unwrap_or(0),
+                service
+            ));
+        }
+        if !keys.iter().any(|k| k.enctype == self.enctype) {
+            let enctypes: Vec<_> = keys.iter().map(|k| enctype_name(k.enctype)).collect();
+            return Some(format!(
+                "ticket enctype {}, keytab has {} for {} kvno {}",
+                enctype_name(self.enctype),
+                enctypes.join(", "),
+                service,
+                kvno
+            ));
+        }
+        None
+    }
+}
+
+/// Summarizes the ticket for logging, e.g. `ticket for HTTP/web@CORP, kvno 7,
+/// aes256-cts-hmac-sha1-96`.
+impl fmt::Display for ApReq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ticket for {}", self.service())?;
+        if let Some(kvno) = self.kvno {
+            write!(f, ", kvno {}", kvno)?;
+        }
+        write!(f, ", {}", enctype_name(self.enctype))
+    }
+}
+
+/// The value of the context-tagged field `tag` of a SEQUENCE.
+fn field(mut fields: &[u8], tag: u8) -> Option<&[u8]> {
+    while !fields.is_empty() {
+        let (f, rest) = der::read(fields)?;
+        if f.tag == tag {
+            return Some(f.value);
+        }
+        fields = rest;
+    }
+    None
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::der::tlv;
+    use crate::keytab::KeytabEntry;
+    use crate::spnego::SPNEGO_OID;
+    use std::time::UNIX_EPOCH;
+
+    fn int(value: &[u8]) -> Vec<u8> {
+        tlv(0x02, value)
+    }
+
+    /// An AP-REQ for `HTTP/web@CORP` as sent by MIT and Windows clients, with an encrypted
+    /// ticket and authenticator of dummy bytes.
+    pub(crate) fn ap_req(enctype: u8, kvno: Option<&[u8]>) -> Vec<u8> {
+        let sname = [
+            tlv(0xa0, &int(&[2])),
+            tlv(
+                0xa1,
+                &tlv(0x30, &[tlv(0x1b, b"HTTP"), tlv(0x1b, b"web")].concat()),
+            ),
+        ]
+        .concat();
+        let mut enc_part = tlv(0xa0, &int(&[enctype]));
+        if let Some(kvno) = kvno {
+            enc_part.extend(tlv(0xa1, &int(kvno)));
+        }
+        enc_part.extend(tlv(0xa2, &tlv(0x04, &[0xee; 64])));
+        let ticket = [
+            tlv(0xa0, &int(&[5])),
+            tlv(0xa1, &tlv(0x1b, b"CORP")),
+            tlv(0xa2, &tlv(0x30, &sname)),
+            tlv(0xa3, &tlv(0x30, &enc_part)),
+        ]
+        .concat();
+        let authenticator = [tlv(0xa0, &int(&[18])), tlv(0xa2, &tlv(0x04, &[0xdd; 32]))].concat();
+        let fields = [
+            tlv(0xa0, &int(&[5])),
+            tlv(0xa1, &int(&[14])),
+            tlv(0xa2, &tlv(0x03, &[0, 0x20, 0, 0, 0])),
+            tlv(0xa3, &tlv(0x61, &tlv(0x30, &ticket))),
+            tlv(0xa4, &tlv(0x30, &authenticator)),
+        ]
+        .concat();
+        tlv(0x6e, &tlv(0x30, &fields))
+    }
+
+    pub(crate) fn gss_token(oid: &[u8], ap_req: &[u8]) -> Vec<u8> {
+        tlv(
+            0x60,
+            &[tlv(0x06, oid), vec![0x01, 0x00], ap_req.to_vec()].concat(),
+        )
+    }
+
+    fn key(principal: &[&str], kvno: u32, enctype: i32) -> KeytabEntry {
+        KeytabEntry {
+            components: principal.iter().map(|c| c.to_string()).collect(),
+            realm: "CORP".to_string(),
+            name_type: Some(1),
+            timestamp: UNIX_EPOCH,
+            kvno,
+            enctype,
+        }
+    }
+
+    #[test]
+    fn parses_ap_req() {
+        let ticket = ApReq::parse(&ap_req(18, Some(&[7]))).unwrap();
+        assert_eq!(
+            ticket,
+            ApReq {
+                realm: "CORP".to_string(),
+                sname: vec!["HTTP".to_string(), "web".to_string()],
+                kvno: Some(7),
+                enctype: 18,
+            }
+        );
+        assert_eq!(ticket.service(), "HTTP/web@CORP");
+        assert_eq!(
+            ticket.to_string(),
+            "ticket for HTTP/web@CORP, kvno 7, aes256-cts-hmac-sha1-96"
+        );
+
+        let ticket = ApReq::parse(&ap_req(23, None)).unwrap();
+        assert_eq!(ticket.kvno, None);
+        assert_eq!(ticket.to_string(), "ticket for HTTP/web@CORP, arcfour-hmac");
+        // Kvnos of 128 and up need a leading zero byte to stay positive
+        let ticket = ApReq::parse(&ap_req(18, Some(&[0x00, 0x80]))).unwrap();
+        assert_eq!(ticket.kvno, Some(128));
+    }
+
+    #[test]
+    fn finds_ap_req_in_tokens() {
+        let bare = ap_req(18, Some(&[7]));
+        let expected = ApReq::parse(&bare);
+        assert!(expected.is_some());
+        assert_eq!(ApReq::from_token(&bare), expected);
+        assert_eq!(ApReq::from_token(&gss_token(KRB5_OID, &bare)), expected);
+        assert_eq!(ApReq::from_token(&gss_token(MS_KRB5_OID, &bare)), expected);
+
+        let mech_types = tlv(0xa0, &tlv(0x30, &tlv(0x06, KRB5_OID)));
+        let mech_token = tlv(0xa2, &tlv(0x04, &gss_token(KRB5_OID, &bare)));
+        let init = tlv(0xa0, &tlv(0x30, &[mech_types, mech_token].concat()));
+        let spnego = tlv(0x60, &[tlv(0x06, SPNEGO_OID), init].concat());
+        assert_eq!(ApReq::from_token(&spnego), expected);
+    }
+
+    #[test]
+    fn ignores_tokens_without_ap_req() {
+        let bare = ap_req(18, Some(&[7]));
+        // A token ID other than AP-REQ, e.g. 03 00 for a KRB-ERROR
+        let error = tlv(
+            0x60,
+            &[tlv(0x06, KRB5_OID), vec![0x03, 0x00], bare.clone()].concat(),
+        );
+        assert_eq!(ApReq::from_token(&error), None);
+        let resp = tlv(0xa1, &tlv(0x30, &tlv(0xa0, &tlv(0x0a, &[1]))));
+        assert_eq!(ApReq::from_token(&resp), None);
+        assert_eq!(ApReq::from_token(b"NTLMSSP\0\x01\0\0\0"), None);
+        assert_eq!(ApReq::from_token(&[]), None);
+        for len in 0..bare.len() {
+            assert_eq!(ApReq::parse(&bare[..len]), None, "{}", len);
+        }
+    }
+
+    #[test]
+    fn reports_kvno_mismatch() {
+        let keytab = Keytab {
+            entries: vec![key(&["HTTP", "web"], 5, 18), key(&["HTTP", "web"], 6, 18)],
+        };
+        let ticket = ApReq::parse(&ap_req(18, Some(&[7]))).unwrap();
+        assert_eq!(
+            ticket.mismatch(&keytab).as_deref(),
+            Some("ticket kvno 7, keytab has kvno 6 for HTTP/web@CORP")
+        );
+        // Tickets for an older key still work while it is in the keytab
+        let ticket = ApReq::parse(&ap_req(18, Some(&[5]))).unwrap();
+        assert_eq!(ticket.mismatch(&keytab), None);
+    }
+
+    #[test]
+    fn reports_enctype_and_principal_mismatch() {
+        let keytab = Keytab {
+            entries: vec![key(&["HTTP", "web"], 7, 17), key(&["HTTP", "web"], 7, 18)],
+        };
+        let ticket = ApReq::parse(&ap_req(23, Some(&[7]))).unwrap();
+        assert_eq!(
+            ticket.mismatch(&keytab).as_deref(),
+            Some(
+                "ticket enctype arcfour-hmac, keytab has aes128-cts-hmac-sha1-96, \
+                 aes256-cts-hmac-sha1-96 for HTTP/web@CORP kvno 7"
+            )
+        );
+        // Without a kvno in the ticket, the latest key is assumed
+        let ticket = ApReq::parse(&ap_req(17, None)).unwrap();
+        assert_eq!(ticket.mismatch(&keytab), None);
+
+        let keytab = Keytab {
+            entries: vec![key(&["HTTP", "www"], 7, 18)],
+        };
+        let ticket = ApReq::parse(&ap_req(18, Some(&[7]))).unwrap();
+        assert_eq!(
+            ticket.mismatch(&keytab).as_deref(),
+            Some("ticket is for HTTP/web@CORP, which is not in the keytab")
+        );
+    }
+
+    #[test]
+    fn names_enctypes() {
+        assert_eq!(enctype_name(18), "aes256-cts-hmac-sha1-96");
+        assert_eq!(enctype_name(20), "aes256-cts-hmac-sha384-192");
+        assert_eq!(enctype_name(-128), "enctype -128");
+        assert!(is_weak_enctype(3));
+        assert!(is_weak_enctype(23));
+        assert!(!is_weak_enctype(17));
+        assert!(!is_weak_enctype(26));
+    }
+}
@@ -1,8 +1,48 @@
+mod accept;
+mod builder;
+mod cache;
+mod config;
+mod cred;
+mod der;
+mod error;
+mod ffi;
+mod group;
 mod guard;
+mod keytab;
+mod krb5;
+mod local;
+mod pac;
+mod policy;
+mod principal;
+mod realm;
+#[doc(hidden)]
+pub mod require;
 mod fairing;
+mod spnego;
+mod store;
+mod token;
+mod watch;
 
-pub use fairing::GssapiFairing;
+pub use accept::AcceptCtx;
+pub use builder::GssapiFairingBuilder;
+pub use config::{ContextFlag, GssapiConfig, Identifier, Mechanism, NameType};
+pub use error::{ConfigError, GssapiError};
+pub use fairing::{GssapiFairing, ResponseBehavior};
+pub use group::{FileGroupResolver, Group, GroupFileError, GroupResolver, InGroup};
 pub use guard::GssapiAuth;
+pub use keytab::{Keytab, KeytabEntry, KeytabError};
+pub use krb5::ApReq;
+pub use local::LocalUser;
+pub use pac::{LogonInfo, Sid, SidError};
+pub use policy::{Decision, PolicyError, PolicyRules, PrincipalPolicy};
+pub use principal::{Principal, PrincipalError};
+pub use realm::RealmPolicy;
+pub use spnego::{NegState, NegToken, NegTokenInit, NegTokenResp, ObjectId, SpnegoError};
+pub use store::{ContextLimits, ContextStore, MemoryStore, PendingContext};
+pub use token::{TokenAction, TokenKind, TokenPolicy};
+#[cfg(feature = "macros")]
+pub use rocket_gssapi_codegen::gssapi_require;
 pub use libgssapi::oid;
 pub use libgssapi::name;
-pub use libgssapi::credential;
\ No newline at end of file
+pub use libgssapi::credential;
+pub use libgssapi::context;
\ No newline at end of file
@@ -0,0 +1,236 @@
+use crate::error::GssapiError;
+use crate::ffi::{gss_localname, GSS_S_COMPLETE};
+use crate::guard::GssapiAuth;
+use libgssapi::name::Name;
+use libgssapi_sys::{gss_buffer_desc, gss_release_buffer};
+use rocket::request::{FromRequest, Outcome};
+use rocket::{error, info, Request};
+use std::ffi::{CStr, CString, OsStr};
+use std::io;
+use std::mem::MaybeUninit;
+use std::ops::Deref;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::ptr;
+
+/// Maps an authenticated name to a local account name with `gss_localname`, which applies
+/// the `auth_to_local` rules of `krb5.conf`.
+pub(crate) fn local_name(name: &Name) -> Option<String> {
+    let mut minor = 0;
+    let mut buf = gss_buffer_desc {
+        length: 0,
+        value: ptr::null_mut(),
+    };
+    let major = unsafe { gss_localname(&mut minor, name.to_c(), ptr::null_mut(), &mut buf) };
+    if major != GSS_S_COMPLETE {
+        return None;
+    }
+    let bytes = unsafe { std::slice::from_raw_parts(buf.value as *const u8, buf.length) };
+    let local = String::from_utf8(bytes.to_vec()).ok();
+    unsafe { gss_release_buffer(&mut minor, &mut buf) };
+    local
+}
+
+/// A request guard for clients that map to a local Unix account, resolved through the system
+/// passwd and group databases. Fails with `403 Forbidden` for authenticated clients without
+/// an account. Dereferences to the [`GssapiAuth`] of the client.
+#[derive(Debug, Clone)]
+pub struct LocalUser {
+    pub auth: GssapiAuth,
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    /// The supplementary groups, including `gid`.
+    pub groups: Vec<u32>,
+    pub home: PathBuf,
+    pub shell: PathBuf,
+}
+impl Deref for LocalUser {
+    type Target = GssapiAuth;
+
+    fn deref(&self) -> &Self::Target {
+        &self.auth
+    }
+}
+
+/// The passwd entry of an account.
+struct Passwd {
+    uid: u32,
+    gid: u32,
+    home: PathBuf,
+    shell: PathBuf,
+}
+
+/// Looks up an account with `getpwnam_r`, returning `None` if there is none.
+fn getpwnam(name: &CStr) -> io::Result<Option<Passwd>> {
+    let len = match unsafe { libc::sysconf(libc::_SC_GETPW_R_SIZE_MAX) } {
+        n if n > 0 => n as usize,
+        _ => 1024,
+    };
+    getpwnam_with(name, len)
+}
+
+/// [`getpwnam`] starting with a buffer of `len` bytes, doubled while it is too small.
+fn getpwnam_with(name: &CStr, mut len: usize) -> io::Result<Option<Passwd>> {
+    loop {
+        let mut buf = vec![0 as libc::c_char; len];
+        let mut pwd = MaybeUninit::<libc::passwd>::uninit();
+        let mut result = ptr::null_mut();
+        let rc = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                pwd.as_mut_ptr(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        if rc == libc::ERANGE && len < 1 << 20 {
+            len = (len * 2).max(1);
+            continue;
+        }
+        // Some platforms report a missing account as an error rather than a null result
+        if rc == libc::ENOENT || rc == libc::ESRCH {
+            return Ok(None);
+        }
+        if rc != 0 {
+            return Err(io::Error::from_raw_os_error(rc));
+        }
+        if result.is_null() {
+            return Ok(None);
+        }
+
+        let pwd = unsafe { pwd.assume_init() };
+        let path = |p: *const libc::c_char| {
+            let bytes = unsafe { CStr::from_ptr(p) }.to_bytes();
+            PathBuf::from(OsStr::from_bytes(bytes))
+        };
+        return Ok(Some(Passwd {
+            uid: pwd.pw_uid,
+            gid: pwd.pw_gid,
+            home: path(pwd.pw_dir),
+            shell: path(pwd.pw_shell),
+        }));
+    }
+}
+
+/// Lists the groups of an account with `getgrouplist`.
+fn getgrouplist(name: &CStr, gid: u32) -> Vec<u32> {
+    getgrouplist_with(name, gid, 32)
+}
+
+/// [`getgrouplist`] starting with room for `len` groups, grown while there are more.
+fn getgrouplist_with(name: &CStr, gid: u32, len: usize) -> Vec<u32> {
+    let mut groups: Vec<libc::gid_t> = vec![0; len];
+    loop {
+        let mut count = groups.len() as libc::c_int;
+        let rc = unsafe {
+            libc::getgrouplist(
+                name.as_ptr(),
+                gid as _,
+                groups.as_mut_ptr() as *mut _,
+                &mut count,
+            )
+        };
+        if rc >= 0 {
+            groups.truncate(count as usize);
+            return groups;
+        }
+        // Updated to the required size, except on platforms that don't
+        let len = (count as usize).max(groups.len() * 2).max(1);
+        if len > 1 << 16 {
+            return vec![gid];
+        }
+        groups.resize(len, 0);
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LocalUser {
+    type Error = GssapiError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let auth = match req.guard::<GssapiAuth>().await {
+            Outcome::Success(a) => a,
+            Outcome::Error(e) => return Outcome::Error(e),
+            Outcome::Forward(s) => return Outcome::Forward(s),
+        };
+        let source = auth.source.clone().unwrap_or_default();
+        let local_name = auth.local_name();
+        let name = match local_name.as_deref().map(CString::new) {
+            Some(Ok(n)) => n,
+            _ => {
+                info!("Kerberos: {} has no local account", source);
+                let e = GssapiError::NoLocalAccount(source);
+                return Outcome::Error((e.status(), e));
+            }
+        };
+
+        // NSS lookups may block on a directory service
+        let lookup = rocket::tokio::task::spawn_blocking(move || {
+            getpwnam(&name).map(|pwd| pwd.map(|pwd| (getgrouplist(&name, pwd.gid), pwd)))
+        })
+        .await
+        .unwrap_or_else(|e| Err(io::Error::other(e)));
+        match lookup {
+            Ok(Some((groups, pwd))) => Outcome::Success(LocalUser {
+                name: local_name.unwrap_or_default(),
+                auth,
+                uid: pwd.uid,
+                gid: pwd.gid,
+                groups,
+                home: pwd.home,
+                shell: pwd.shell,
+            }),
+            Ok(None) => {
+                info!("Kerberos: {} maps to a nonexistent local account", source);
+                let e = GssapiError::NoLocalAccount(source);
+                Outcome::Error((e.status(), e))
+            }
+            Err(e) => {
+                error!("Kerberos: Failed to look up local account of {}: {}", source, e);
+                let e = GssapiError::AccountLookup(e.to_string());
+                Outcome::Error((e.status(), e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root() -> CString {
+        CString::new("root").unwrap()
+    }
+
+    #[test]
+    fn looks_up_root() {
+        let pwd = getpwnam(&root()).unwrap().unwrap();
+        assert_eq!((pwd.uid, pwd.gid), (0, 0));
+        assert!(getgrouplist(&root(), pwd.gid).contains(&0));
+    }
+
+    #[test]
+    fn grows_the_passwd_buffer() {
+        for len in [0, 1] {
+            let pwd = getpwnam_with(&root(), len).unwrap().unwrap();
+            assert_eq!((pwd.uid, pwd.gid), (0, 0));
+            assert_eq!(pwd.home, getpwnam(&root()).unwrap().unwrap().home);
+        }
+    }
+
+    #[test]
+    fn grows_the_group_list() {
+        for len in [0, 1] {
+            assert_eq!(getgrouplist_with(&root(), 0, len), getgrouplist(&root(), 0));
+        }
+    }
+
+    #[test]
+    fn unknown_accounts_are_none() {
+        let name = CString::new("rocket-gssapi-no-such-user").unwrap();
+        assert!(getpwnam(&name).unwrap().is_none());
+        assert!(getpwnam_with(&name, 1).unwrap().is_none());
+    }
+}
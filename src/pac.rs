@@ -0,0 +1,713 @@
+//! Decoder for the logon information in the PAC of tickets issued by Active Directory.
+use crate::ffi::{gss_get_name_attribute, GSS_S_COMPLETE};
+use libgssapi::name::Name;
+use libgssapi_sys::{gss_buffer_desc, gss_release_buffer};
+use rocket::debug;
+use std::fmt;
+use std::os::raw::c_void;
+use std::ptr;
+use std::str::FromStr;
+
+/// The naming extension attribute holding the `KERB_VALIDATION_INFO` of the PAC.
+const LOGON_INFO_ATTRIBUTE: &[u8] = b"urn:mspac:logon-info";
+
+/// A Windows security identifier, such as `S-1-5-21-1004336348-1177238915-682003330-512`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Sid {
+    revision: u8,
+    authority: u64,
+    sub_authorities: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SidError;
+
+impl fmt::Display for SidError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid SID")
+    }
+}
+
+impl std::error::Error for SidError {}
+
+impl Sid {
+    pub fn new(authority: u64, sub_authorities: Vec<u32>) -> Sid {
+        Sid {
+            revision: 1,
+            authority,
+            sub_authorities,
+        }
+    }
+
+    pub fn authority(&self) -> u64 {
+        self.authority
+    }
+
+    pub fn sub_authorities(&self) -> &[u32] {
+        &self.sub_authorities
+    }
+
+    /// The relative identifier, the last sub-authority.
+    pub fn rid(&self) -> Option<u32> {
+        self.sub_authorities.last().copied()
+    }
+
+    /// The SID of the account `rid` in the domain identified by this SID.
+    pub fn with_rid(&self, rid: u32) -> Sid {
+        let mut sid = self.clone();
+        sid.sub_authorities.push(rid);
+        sid
+    }
+}
+
+impl fmt::Display for Sid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "S-{}-", self.revision)?;
+        if self.authority >> 32 == 0 {
+            write!(f, "{}", self.authority)?;
+        } else {
+            write!(f, "0x{:012X}", self.authority)?;
+        }
+        for s in &self.sub_authorities {
+            write!(f, "-{}", s)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Sid {
+    type Err = SidError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.strip_prefix("S-").ok_or(SidError)?.split('-');
+        let revision = parts.next().and_then(|r| r.parse().ok()).ok_or(SidError)?;
+        let authority = parts.next().ok_or(SidError)?;
+        let authority = match authority.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => authority.parse(),
+        }
+        .map_err(|_| SidError)?;
+        if authority >> 48 != 0 {
+            return Err(SidError);
+        }
+        let sub_authorities = parts
+            .map(|p| p.parse().map_err(|_| SidError))
+            .collect::<Result<Vec<u32>, _>>()?;
+        if sub_authorities.len() > 15 {
+            return Err(SidError);
+        }
+        Ok(Sid {
+            revision,
+            authority,
+            sub_authorities,
+        })
+    }
+}
+
+/// The logon information of an Active Directory PAC: who the client is and which groups it
+/// belongs to, as vouched for by the domain controller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogonInfo {
+    effective_name: String,
+    logon_domain: String,
+    domain_sid: Sid,
+    user_sid: Sid,
+    primary_group_sid: Sid,
+    group_sids: Vec<Sid>,
+}
+
+impl LogonInfo {
+    /// The account name, e.g. `alice`.
+    pub fn effective_name(&self) -> &str {
+        &self.effective_name
+    }
+
+    /// The NetBIOS name of the domain the client logged on to, e.g. `CORP`.
+    pub fn logon_domain(&self) -> &str {
+        &self.logon_domain
+    }
+
+    pub fn domain_sid(&self) -> &Sid {
+        &self.domain_sid
+    }
+
+    pub fn user_sid(&self) -> &Sid {
+        &self.user_sid
+    }
+
+    pub fn primary_group_sid(&self) -> &Sid {
+        &self.primary_group_sid
+    }
+
+    /// All groups the client is a member of, including the primary group, extra SIDs and
+    /// resource groups.
+    pub fn group_sids(&self) -> &[Sid] {
+        &self.group_sids
+    }
+
+    pub fn is_member_of(&self, group: &Sid) -> bool {
+        self.group_sids.contains(group)
+    }
+
+    /// Reads the logon information from the PAC of an authenticated name, if the ticket had
+    /// one whose signature was verified.
+    pub(crate) fn of(name: &Name) -> Option<LogonInfo> {
+        let mut minor = 0;
+        let mut attr = gss_buffer_desc {
+            length: LOGON_INFO_ATTRIBUTE.len(),
+            value: LOGON_INFO_ATTRIBUTE.as_ptr() as *mut c_void,
+        };
+        let mut authenticated = 0;
+        let mut complete = 0;
+        let mut value = gss_buffer_desc {
+            length: 0,
+            value: ptr::null_mut(),
+        };
+        let mut more = -1;
+        let major = unsafe {
+            gss_get_name_attribute(
+                &mut minor,
+                name.to_c(),
+                &mut attr,
+                &mut authenticated,
+                &mut complete,
+                &mut value,
+                ptr::null_mut(),
+                &mut more,
+            )
+        };
+        if major != GSS_S_COMPLETE {
+            return None;
+        }
+
+        let info = if authenticated == 0 {
+            debug!("Kerberos: Ignoring PAC with unverified signature");
+            None
+        } else {
+            let data =
+                unsafe { std::slice::from_raw_parts(value.value as *const u8, value.length) };
+            LogonInfo::parse(data)
+                .inspect_err(|e| debug!("Kerberos: Failed to decode PAC logon info: {}", e))
+                .ok()
+        };
+        unsafe { gss_release_buffer(&mut minor, &mut value) };
+        info
+    }
+
+    /// Decodes an NDR-serialized `KERB_VALIDATION_INFO` structure, see MS-PAC 2.5.
+    pub(crate) fn parse(data: &[u8]) -> Result<LogonInfo, PacError> {
+        let mut r = Ndr::new(data)?;
+        // Referent of the top-level pointer
+        r.u32()?;
+
+        // LogonTime, LogoffTime, KickOffTime, PasswordLastSet, PasswordCanChange and
+        // PasswordMustChange
+        r.skip(6 * 8)?;
+        let effective_name = r.unicode_string()?;
+        let other_names = [
+            r.unicode_string()?, // FullName
+            r.unicode_string()?, // LogonScript
+            r.unicode_string()?, // ProfilePath
+            r.unicode_string()?, // HomeDirectory
+            r.unicode_string()?, // HomeDirectoryDrive
+        ];
+        // LogonCount, BadPasswordCount
+        r.skip(2 * 2)?;
+        let user_id = r.u32()?;
+        let primary_group_id = r.u32()?;
+        let _group_count = r.u32()?;
+        let group_ids = r.u32()?;
+        // UserFlags, UserSessionKey
+        r.skip(4 + 16)?;
+        let logon_server = r.unicode_string()?;
+        let logon_domain_name = r.unicode_string()?;
+        let logon_domain_id = r.u32()?;
+        // Reserved1, UserAccountControl, SubAuthStatus, LastSuccessfulILogon,
+        // LastFailedILogon, FailedILogonCount and Reserved3
+        r.skip(8 + 4 + 4 + 8 + 8 + 4 + 4)?;
+        let _sid_count = r.u32()?;
+        let extra_sids = r.u32()?;
+        let resource_group_domain_sid = r.u32()?;
+        let _resource_group_count = r.u32()?;
+        let resource_group_ids = r.u32()?;
+
+        // The referents follow the structure in the order of their pointers
+        let effective_name = r.deferred_string(effective_name)?;
+        for name in other_names {
+            r.deferred_string(name)?;
+        }
+        let groups = r.deferred_group_ids(group_ids)?;
+        r.deferred_string(logon_server)?;
+        let logon_domain = r.deferred_string(logon_domain_name)?;
+        if logon_domain_id == 0 {
+            return Err(PacError::MissingDomainSid);
+        }
+        let domain_sid = r.sid()?;
+        let mut extra = Vec::new();
+        if extra_sids != 0 {
+            let count = r.u32()?;
+            let mut pointers = Vec::new();
+            for _ in 0..count {
+                let sid = r.u32()?;
+                let _attributes = r.u32()?;
+                pointers.push(sid);
+            }
+            for _ in pointers.into_iter().filter(|&p| p != 0) {
+                extra.push(r.sid()?);
+            }
+        }
+        let resource_domain_sid = match resource_group_domain_sid {
+            0 => None,
+            _ => Some(r.sid()?),
+        };
+        let resource_groups = r.deferred_group_ids(resource_group_ids)?;
+
+        let primary_group_sid = domain_sid.with_rid(primary_group_id);
+        let mut group_sids = vec![primary_group_sid.clone()];
+        let resource_group_sids = resource_domain_sid
+            .iter()
+            .flat_map(|d| resource_groups.iter().map(|&rid| d.with_rid(rid)));
+        for sid in groups
+            .iter()
+            .map(|&rid| domain_sid.with_rid(rid))
+            .chain(extra)
+            .chain(resource_group_sids)
+        {
+            if !group_sids.contains(&sid) {
+                group_sids.push(sid);
+            }
+        }
+
+        Ok(LogonInfo {
+            effective_name,
+            logon_domain,
+            user_sid: domain_sid.with_rid(user_id),
+            domain_sid,
+            primary_group_sid,
+            group_sids,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum PacError {
+    /// The buffer doesn't start with a little-endian NDR type serialization header.
+    BadHeader,
+    /// The structure runs past the end of the buffer.
+    Truncated,
+    /// The logon information has no domain SID to derive the other SIDs from.
+    MissingDomainSid,
+}
+
+impl fmt::Display for PacError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PacError::BadHeader => write!(f, "unsupported NDR serialization header"),
+            PacError::Truncated => write!(f, "logon info is truncated"),
+            PacError::MissingDomainSid => write!(f, "logon info has no domain SID"),
+        }
+    }
+}
+
+impl std::error::Error for PacError {}
+
+/// Reads little-endian NDR data, aligning each primitive to its size.
+struct Ndr<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Ndr<'a> {
+    /// Skips the type serialization version 1 header, see MS-RPCE 2.2.6.
+    fn new(buf: &'a [u8]) -> Result<Ndr<'a>, PacError> {
+        match buf {
+            [1, 0x10, 8, 0, ..] if buf.len() >= 16 => Ok(Ndr { buf, pos: 16 }),
+            _ => Err(PacError::BadHeader),
+        }
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], PacError> {
+        let end = self.pos.checked_add(n).ok_or(PacError::Truncated)?;
+        let b = self.buf.get(self.pos..end).ok_or(PacError::Truncated)?;
+        self.pos = end;
+        Ok(b)
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), PacError> {
+        self.bytes(n).map(|_| ())
+    }
+
+    fn align(&mut self, n: usize) {
+        self.pos = self.pos.next_multiple_of(n);
+    }
+
+    fn u8(&mut self) -> Result<u8, PacError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, PacError> {
+        self.align(2);
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, PacError> {
+        self.align(4);
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// Reads an `RPC_UNICODE_STRING`, returning the pointer to its characters.
+    fn unicode_string(&mut self) -> Result<u32, PacError> {
+        let _length = self.u16()?;
+        let _maximum_length = self.u16()?;
+        self.u32()
+    }
+
+    /// Reads the characters of an `RPC_UNICODE_STRING`, a conformant varying array.
+    fn deferred_string(&mut self, pointer: u32) -> Result<String, PacError> {
+        if pointer == 0 {
+            return Ok(String::new());
+        }
+        let _max_count = self.u32()?;
+        let _offset = self.u32()?;
+        let count = self.u32()? as usize;
+        let chars = self.bytes(count.checked_mul(2).ok_or(PacError::Truncated)?)?;
+        let chars: Vec<u16> = chars
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        Ok(String::from_utf16_lossy(&chars))
+    }
+
+    /// Reads the relative IDs of a `GROUP_MEMBERSHIP` array.
+    fn deferred_group_ids(&mut self, pointer: u32) -> Result<Vec<u32>, PacError> {
+        let mut rids = Vec::new();
+        if pointer != 0 {
+            let count = self.u32()?;
+            for _ in 0..count {
+                rids.push(self.u32()?);
+                let _attributes = self.u32()?;
+            }
+        }
+        Ok(rids)
+    }
+
+    /// Reads an `RPC_SID`, whose sub-authority count precedes it as the conformance.
+    fn sid(&mut self) -> Result<Sid, PacError> {
+        let _max_count = self.u32()?;
+        let revision = self.u8()?;
+        let count = self.u8()?;
+        let authority = self.bytes(6)?.iter().fold(0u64, |a, &b| a << 8 | b as u64);
+        let sub_authorities = (0..count)
+            .map(|_| self.u32())
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Sid {
+            revision,
+            authority,
+            sub_authorities,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// The parts of a `KERB_VALIDATION_INFO` the decoder looks at.
+    pub(crate) struct Fixture {
+        domain_sid: Option<Sid>,
+        groups: Vec<u32>,
+        group_count: Option<u32>,
+        extra_sids: Vec<Sid>,
+        resource_domain_sid: Option<Sid>,
+        resource_groups: Vec<u32>,
+    }
+
+    impl Default for Fixture {
+        fn default() -> Self {
+            Fixture {
+                domain_sid: Some("S-1-5-21-1004336348-1177238915-682003330".parse().unwrap()),
+                groups: vec![513, 512, 520],
+                group_count: None,
+                extra_sids: vec![],
+                resource_domain_sid: None,
+                resource_groups: vec![],
+            }
+        }
+    }
+
+    /// Serializes NDR the way the domain controller does: little-endian, naturally aligned,
+    /// with the referents of pointers deferred after the structure.
+    struct Writer(Vec<u8>);
+
+    impl Writer {
+        fn align(&mut self, n: usize) {
+            self.0.resize(self.0.len().next_multiple_of(n), 0);
+        }
+
+        fn u16(&mut self, v: u16) {
+            self.align(2);
+            self.0.extend(v.to_le_bytes());
+        }
+
+        fn u32(&mut self, v: u32) {
+            self.align(4);
+            self.0.extend(v.to_le_bytes());
+        }
+
+        fn pointer(&mut self, present: bool) {
+            self.u32(if present { 0x0002_0000 } else { 0 });
+        }
+
+        fn unicode_string(&mut self, s: &str) {
+            let length = s.encode_utf16().count() as u16 * 2;
+            self.u16(length);
+            self.u16(length);
+            self.pointer(!s.is_empty());
+        }
+
+        fn deferred_string(&mut self, s: &str) {
+            if s.is_empty() {
+                return;
+            }
+            let count = s.encode_utf16().count() as u32;
+            self.u32(count);
+            self.u32(0);
+            self.u32(count);
+            for c in s.encode_utf16() {
+                self.0.extend(c.to_le_bytes());
+            }
+        }
+
+        fn group_ids(&mut self, count: u32, rids: &[u32]) {
+            self.u32(count);
+            for &rid in rids {
+                // SE_GROUP_MANDATORY | SE_GROUP_ENABLED_BY_DEFAULT | SE_GROUP_ENABLED
+                self.u32(rid);
+                self.u32(7);
+            }
+        }
+
+        fn sid(&mut self, sid: &Sid) {
+            self.u32(sid.sub_authorities.len() as u32);
+            self.0.push(sid.revision);
+            self.0.push(sid.sub_authorities.len() as u8);
+            self.0.extend(&sid.authority.to_be_bytes()[2..]);
+            for &s in &sid.sub_authorities {
+                self.u32(s);
+            }
+        }
+    }
+
+    /// Encodes the fixture in the layout of MS-PAC 2.5 behind a type serialization header.
+    pub(crate) fn encode(f: &Fixture) -> Vec<u8> {
+        let mut w = Writer(vec![1, 0x10, 8, 0, 0xcc, 0xcc, 0xcc, 0xcc]);
+        w.u32(0); // length of the serialized data, unchecked
+        w.u32(0);
+        w.pointer(true);
+        for _ in 0..6 {
+            w.u32(0xffff_ffff);
+            w.u32(0x7fff_ffff);
+        }
+        let names = ["alice", "Alice Liddell", "", "", "", ""];
+        for name in names {
+            w.unicode_string(name);
+        }
+        w.u16(12); // LogonCount
+        w.u16(0); // BadPasswordCount
+        w.u32(1105);
+        w.u32(513);
+        w.u32(f.groups.len() as u32);
+        w.pointer(true);
+        w.u32(0x20); // LOGON_EXTRA_SIDS
+        for _ in 0..4 {
+            w.u32(0);
+        }
+        w.unicode_string("DC1");
+        w.unicode_string("CORP");
+        w.pointer(f.domain_sid.is_some());
+        for _ in 0..10 {
+            w.u32(0);
+        }
+        w.u32(f.extra_sids.len() as u32);
+        w.pointer(!f.extra_sids.is_empty());
+        w.pointer(f.resource_domain_sid.is_some());
+        w.u32(f.resource_groups.len() as u32);
+        w.pointer(f.resource_domain_sid.is_some());
+
+        for name in names {
+            w.deferred_string(name);
+        }
+        w.group_ids(f.group_count.unwrap_or(f.groups.len() as u32), &f.groups);
+        w.deferred_string("DC1");
+        w.deferred_string("CORP");
+        if let Some(sid) = &f.domain_sid {
+            w.sid(sid);
+        }
+        if !f.extra_sids.is_empty() {
+            w.u32(f.extra_sids.len() as u32);
+            for _ in &f.extra_sids {
+                w.pointer(true);
+                w.u32(7);
+            }
+            for sid in &f.extra_sids {
+                w.sid(sid);
+            }
+        }
+        if let Some(sid) = &f.resource_domain_sid {
+            w.sid(sid);
+            w.group_ids(f.resource_groups.len() as u32, &f.resource_groups);
+        }
+        w.0
+    }
+
+    fn sids(s: &[&str]) -> Vec<Sid> {
+        s.iter().map(|s| s.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn decodes_logon_info() {
+        let info = LogonInfo::parse(&encode(&Fixture::default())).unwrap();
+        assert_eq!(info.effective_name(), "alice");
+        assert_eq!(info.logon_domain(), "CORP");
+        let domain = "S-1-5-21-1004336348-1177238915-682003330";
+        assert_eq!(info.domain_sid().to_string(), domain);
+        assert_eq!(info.user_sid().to_string(), format!("{}-1105", domain));
+        assert_eq!(
+            info.primary_group_sid().to_string(),
+            format!("{}-513", domain)
+        );
+        // The primary group is listed once although it is in the group IDs as well
+        let groups: Vec<String> = info.group_sids().iter().map(Sid::to_string).collect();
+        let expected: Vec<String> = [513, 512, 520]
+            .iter()
+            .map(|rid| format!("{}-{}", domain, rid))
+            .collect();
+        assert_eq!(groups, expected);
+    }
+
+    #[test]
+    fn decodes_extra_sids_and_resource_groups() {
+        let fixture = Fixture {
+            extra_sids: sids(&["S-1-18-1", "S-1-5-21-1-2-3-1108"]),
+            resource_domain_sid: Some("S-1-5-21-7-8-9".parse().unwrap()),
+            resource_groups: vec![1201, 1202],
+            ..Fixture::default()
+        };
+        let info = LogonInfo::parse(&encode(&fixture)).unwrap();
+        let domain = info.domain_sid().clone();
+        let mut expected: Vec<Sid> = [513, 512, 520]
+            .iter()
+            .map(|&r| domain.with_rid(r))
+            .collect();
+        expected.extend(sids(&[
+            "S-1-18-1",
+            "S-1-5-21-1-2-3-1108",
+            "S-1-5-21-7-8-9-1201",
+            "S-1-5-21-7-8-9-1202",
+        ]));
+        assert_eq!(info.group_sids(), expected);
+        assert!(info.is_member_of(&"S-1-5-21-7-8-9-1202".parse().unwrap()));
+        assert!(!info.is_member_of(&"S-1-5-21-7-8-9-1203".parse().unwrap()));
+    }
+
+    #[test]
+    fn decodes_without_groups() {
+        let fixture = Fixture {
+            groups: vec![],
+            ..Fixture::default()
+        };
+        let info = LogonInfo::parse(&encode(&fixture)).unwrap();
+        assert_eq!(info.group_sids(), [info.primary_group_sid().clone()]);
+    }
+
+    #[test]
+    fn rejects_bad_header() {
+        let mut data = encode(&Fixture::default());
+        assert_eq!(LogonInfo::parse(&data[..8]), Err(PacError::BadHeader));
+        // Big-endian data representation
+        data[1] = 0x00;
+        assert_eq!(LogonInfo::parse(&data), Err(PacError::BadHeader));
+        assert_eq!(LogonInfo::parse(&[]), Err(PacError::BadHeader));
+    }
+
+    #[test]
+    fn rejects_truncated_buffer() {
+        let fixture = Fixture {
+            extra_sids: sids(&["S-1-18-1"]),
+            resource_domain_sid: Some("S-1-5-21-7-8-9".parse().unwrap()),
+            resource_groups: vec![1201],
+            ..Fixture::default()
+        };
+        let data = encode(&fixture);
+        for len in 16..data.len() {
+            assert_eq!(
+                LogonInfo::parse(&data[..len]),
+                Err(PacError::Truncated),
+                "{}",
+                len
+            );
+        }
+        assert!(LogonInfo::parse(&data).is_ok());
+    }
+
+    #[test]
+    fn rejects_bad_referents() {
+        let fixture = Fixture {
+            domain_sid: None,
+            ..Fixture::default()
+        };
+        let data = encode(&fixture);
+        assert_eq!(LogonInfo::parse(&data), Err(PacError::MissingDomainSid));
+
+        // A group count beyond the end of the buffer
+        let fixture = Fixture {
+            group_count: Some(u32::MAX),
+            ..Fixture::default()
+        };
+        let data = encode(&fixture);
+        assert_eq!(LogonInfo::parse(&data), Err(PacError::Truncated));
+    }
+
+    #[test]
+    fn sid_round_trip() {
+        for s in [
+            "S-1-5-21-1004336348-1177238915-682003330-512",
+            "S-1-5-32-544",
+            "S-1-1-0",
+            "S-1-5",
+            "S-1-0x123456789ABC-1",
+        ] {
+            let sid: Sid = s.parse().unwrap();
+            assert_eq!(sid.to_string(), s);
+        }
+        let sid: Sid = "S-1-5-21-1-2-3".parse().unwrap();
+        assert_eq!(sid, Sid::new(5, vec![21, 1, 2, 3]));
+        assert_eq!(sid.authority(), 5);
+        assert_eq!(sid.rid(), Some(3));
+        assert_eq!(sid.with_rid(512).to_string(), "S-1-5-21-1-2-3-512");
+        assert_eq!(Sid::new(5, vec![]).rid(), None);
+        // Small authorities written in hex are displayed in decimal
+        assert_eq!("S-1-0x5-18".parse::<Sid>().unwrap().to_string(), "S-1-5-18");
+    }
+
+    #[test]
+    fn rejects_invalid_sids() {
+        for s in [
+            "",
+            "S-1",
+            "s-1-5-18",
+            "S-x-5-18",
+            "S-1-5-",
+            "S-1-5-18-",
+            "S-1-5-4294967296",
+            "S-1-281474976710656-1",
+            "S-1-0x1000000000000-1",
+            "S-1-5-1-2-3-4-5-6-7-8-9-10-11-12-13-14-15-16",
+            "SID-1-5",
+        ] {
+            assert_eq!(s.parse::<Sid>(), Err(SidError), "{}", s);
+        }
+    }
+}
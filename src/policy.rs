@@ -0,0 +1,252 @@
+use rocket::figment::providers::{Format, Toml};
+use rocket::figment::{self, Figment};
+use rocket::serde::Deserialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+/// A policy file could not be read or parsed.
+#[derive(Debug)]
+pub struct PolicyError(pub(crate) Box<figment::Error>);
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid policy file: {}", self.0)
+    }
+}
+
+impl std::error::Error for PolicyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&*self.0)
+    }
+}
+
+/// The outcome for principals not matched by any rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum Decision {
+    Allow,
+    Deny,
+}
+
+/// Allow and deny rules for authenticated principals, as found in a policy file:
+///
+/// ```toml
+/// allow = ["*/admin@CORP.EXAMPLE", "svc-*@CORP.EXAMPLE"]
+/// deny = ["svc-legacy@CORP.EXAMPLE"]
+/// ```
+///
+/// Patterns are matched against the principal in its display form, where `*` matches any part
+/// of a single component or the realm, but not across `/` or `@`. Realms are compared
+/// case-insensitively. Deny rules take precedence over allow rules. Principals matching neither
+/// get the `default` decision, which is to deny if there are allow rules and to allow otherwise.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PolicyRules {
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+    #[serde(default)]
+    pub default: Option<Decision>,
+}
+impl PolicyRules {
+    pub fn allows(&self, principal: &str) -> bool {
+        if self.deny.iter().any(|p| matches(p, principal)) {
+            return false;
+        }
+        if self.allow.iter().any(|p| matches(p, principal)) {
+            return true;
+        }
+        match self.default {
+            Some(d) => d == Decision::Allow,
+            None => self.allow.is_empty(),
+        }
+    }
+}
+
+/// Matches the principal `name` against `pattern`, comparing the realms case-insensitively like
+/// [`RealmPolicy`](crate::RealmPolicy) does.
+fn matches(pattern: &str, name: &str) -> bool {
+    match (split_realm(pattern), split_realm(name)) {
+        ((pattern, Some(pattern_realm)), (name, Some(realm))) => {
+            glob(pattern, name)
+                && glob(
+                    &pattern_realm.to_ascii_uppercase(),
+                    &realm.to_ascii_uppercase(),
+                )
+        }
+        ((pattern, None), (name, None)) => glob(pattern, name),
+        _ => false,
+    }
+}
+
+/// Splits a principal in display form at the first unescaped `@`.
+fn split_realm(name: &str) -> (&str, Option<&str>) {
+    let mut escaped = false;
+    for (i, c) in name.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '@' => return (&name[..i], Some(&name[i + 1..])),
+            _ => {}
+        }
+    }
+    (name, None)
+}
+
+/// Matches `name` against `pattern`, where `*` matches any sequence without `/` or `@`.
+fn glob(pattern: &str, name: &str) -> bool {
+    let (pattern, name) = (pattern.as_bytes(), name.as_bytes());
+    let (mut p, mut n) = (0, 0);
+    // Where to resume if the last `*` has to match more
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, n));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == name[n] {
+            p += 1;
+            n += 1;
+        } else if let Some((sp, sn)) = star.filter(|&(_, sn)| !matches!(name[sn], b'/' | b'@')) {
+            star = Some((sp, sn + 1));
+            p = sp + 1;
+            n = sn + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Principal allow and deny rules, optionally loaded from a TOML file that can be reloaded
+/// while Rocket is running. See [`PolicyRules`] for the format.
+#[derive(Debug, Default)]
+pub struct PrincipalPolicy {
+    path: Option<PathBuf>,
+    rules: RwLock<PolicyRules>,
+}
+impl PrincipalPolicy {
+    pub fn new(rules: PolicyRules) -> PrincipalPolicy {
+        PrincipalPolicy {
+            path: None,
+            rules: RwLock::new(rules),
+        }
+    }
+
+    /// Loads the rules from a TOML file.
+    pub fn load(path: impl Into<PathBuf>) -> Result<PrincipalPolicy, PolicyError> {
+        let path = path.into();
+        let rules = PrincipalPolicy::read(&path)?;
+        Ok(PrincipalPolicy {
+            path: Some(path),
+            rules: RwLock::new(rules),
+        })
+    }
+
+    fn read(path: &Path) -> Result<PolicyRules, PolicyError> {
+        Figment::from(Toml::file_exact(path))
+            .extract()
+            .map_err(|e| PolicyError(Box::new(e)))
+    }
+
+    /// The file the rules were loaded from, if any.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Reloads the rules from the file. The old rules stay in effect if the file is invalid.
+    pub fn reload(&self) -> Result<(), PolicyError> {
+        if let Some(path) = &self.path {
+            let rules = PrincipalPolicy::read(path)?;
+            if let Ok(mut r) = self.rules.write() {
+                *r = rules;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `principal` is allowed by the current rules.
+    pub fn allows(&self, principal: &str) -> bool {
+        self.rules.read().is_ok_and(|r| r.allows(principal))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(allow: &[&str], deny: &[&str], default: Option<Decision>) -> PolicyRules {
+        PolicyRules {
+            allow: allow.iter().map(|s| s.to_string()).collect(),
+            deny: deny.iter().map(|s| s.to_string()).collect(),
+            default,
+        }
+    }
+
+    #[test]
+    fn stars_match_within_a_component_or_realm() {
+        assert!(matches("*@CORP.EXAMPLE", "alice@CORP.EXAMPLE"));
+        assert!(!matches("*@CORP.EXAMPLE", "alice/admin@CORP.EXAMPLE"));
+        assert!(matches("*/admin@CORP.EXAMPLE", "alice/admin@CORP.EXAMPLE"));
+        assert!(matches("svc-*@CORP.EXAMPLE", "svc-backup@CORP.EXAMPLE"));
+        assert!(!matches("svc-*@CORP.EXAMPLE", "svc@CORP.EXAMPLE"));
+        assert!(matches("*a*e@*", "alice@CORP.EXAMPLE"));
+        assert!(matches("alice@*.EXAMPLE", "alice@CORP.EXAMPLE"));
+        assert!(!matches("alice@*", "alice"));
+        assert!(matches("alice", "alice"));
+        assert!(!matches("alice", "alice@CORP.EXAMPLE"));
+    }
+
+    #[test]
+    fn realms_match_case_insensitively() {
+        assert!(matches("alice@corp.example", "alice@CORP.EXAMPLE"));
+        assert!(matches("*@Corp.*", "alice@CORP.EXAMPLE"));
+        assert!(!matches("Alice@CORP.EXAMPLE", "alice@CORP.EXAMPLE"));
+    }
+
+    #[test]
+    fn escaped_at_signs_stay_in_the_component() {
+        assert!(matches(r"a\@b@CORP", r"a\@b@corp"));
+        assert!(!matches(r"a\@b@CORP", r"a\@B@corp"));
+        assert_eq!(split_realm(r"a\@b@CORP"), (r"a\@b", Some("CORP")));
+    }
+
+    #[test]
+    fn deny_rules_win_over_allow_rules() {
+        let r = rules(&["svc-*@CORP"], &["svc-legacy@CORP"], None);
+        assert!(r.allows("svc-backup@CORP"));
+        assert!(!r.allows("svc-legacy@CORP"));
+    }
+
+    #[test]
+    fn default_depends_on_allow_rules() {
+        assert!(rules(&[], &[], None).allows("alice@CORP"));
+        assert!(!rules(&[], &["bob@CORP"], None).allows("bob@CORP"));
+        assert!(rules(&[], &["bob@CORP"], None).allows("alice@CORP"));
+        assert!(!rules(&["bob@CORP"], &[], None).allows("alice@CORP"));
+        assert!(rules(&["bob@CORP"], &[], Some(Decision::Allow)).allows("alice@CORP"));
+        assert!(!rules(&[], &[], Some(Decision::Deny)).allows("alice@CORP"));
+    }
+
+    #[test]
+    fn loads_and_reloads_files() {
+        let name = format!("rocket-gssapi-policy-{}.toml", std::process::id());
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, "allow = [\"alice@CORP\"]\n").unwrap();
+        let policy = PrincipalPolicy::load(&path).unwrap();
+        assert!(policy.allows("alice@CORP"));
+        assert!(!policy.allows("bob@CORP"));
+
+        std::fs::write(&path, "allow = [\"bob@CORP\"]\n").unwrap();
+        policy.reload().unwrap();
+        assert!(policy.allows("bob@CORP"));
+
+        // Invalid files keep the old rules in effect
+        std::fs::write(&path, "allow = 1\n").unwrap();
+        assert!(policy.reload().is_err());
+        assert!(policy.allows("bob@CORP"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
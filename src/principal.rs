@@ -0,0 +1,250 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// A Kerberos principal name, parsed from its RFC 1964 display form such as
+/// `HTTP/www.example.com@EXAMPLE.COM`.
+///
+/// Components are separated by `/` and the realm follows the first `@`. A backslash escapes
+/// `/`, `@` and itself, while `\n`, `\t`, `\b` and `\0` stand for the respective control
+/// characters.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Principal {
+    components: Vec<String>,
+    realm: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrincipalError {
+    /// The name has no components.
+    Empty,
+    /// The name contains more than one unescaped `@`.
+    MultipleRealms,
+    /// The name ends in an unfinished escape sequence.
+    TrailingEscape,
+}
+
+impl fmt::Display for PrincipalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrincipalError::Empty => write!(f, "principal name is empty"),
+            PrincipalError::MultipleRealms => write!(f, "principal name has multiple realms"),
+            PrincipalError::TrailingEscape => write!(f, "principal name ends in an escape"),
+        }
+    }
+}
+
+impl std::error::Error for PrincipalError {}
+
+impl Principal {
+    pub fn new(components: Vec<String>, realm: Option<String>) -> Result<Principal, PrincipalError> {
+        if components.is_empty() {
+            return Err(PrincipalError::Empty);
+        }
+        Ok(Principal { components, realm })
+    }
+
+    /// Parses a principal from its display form.
+    pub fn parse(name: &str) -> Result<Principal, PrincipalError> {
+        let mut components = Vec::new();
+        let mut current = String::new();
+        let mut in_realm = false;
+
+        let mut chars = name.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => current.push(match chars.next() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('b') => '\x08',
+                    Some('0') => '\0',
+                    Some(c) => c,
+                    None => return Err(PrincipalError::TrailingEscape),
+                }),
+                '/' if !in_realm => components.push(std::mem::take(&mut current)),
+                '@' if in_realm => return Err(PrincipalError::MultipleRealms),
+                '@' => {
+                    components.push(std::mem::take(&mut current));
+                    in_realm = true;
+                }
+                c => current.push(c),
+            }
+        }
+        let realm = if in_realm {
+            Some(current)
+        } else {
+            components.push(current);
+            None
+        };
+
+        if components.len() == 1 && components[0].is_empty() {
+            return Err(PrincipalError::Empty);
+        }
+        Principal::new(components, realm)
+    }
+
+    /// All name components, e.g. `["HTTP", "www.example.com"]`.
+    pub fn components(&self) -> &[String] {
+        &self.components
+    }
+
+    /// The first component, the user name of user principals or the service of service
+    /// principals.
+    pub fn primary(&self) -> &str {
+        &self.components[0]
+    }
+
+    /// The second component, such as the host of a service principal or the `admin` of
+    /// `alice/admin`.
+    pub fn instance(&self) -> Option<&str> {
+        self.components.get(1).map(String::as_str)
+    }
+
+    /// The user name, which is the primary component. Note that `alice/admin` has the user
+    /// name `alice` too, check [`Principal::instance()`] if that matters.
+    pub fn username(&self) -> &str {
+        self.primary()
+    }
+
+    pub fn realm(&self) -> Option<&str> {
+        self.realm.as_deref()
+    }
+
+    /// Whether this looks like a service principal, i.e. `service/host` with exactly two
+    /// components where the service is a well-known one, such as `HTTP` or `host`, or the host
+    /// looks like a domain name.
+    ///
+    /// This is a heuristic, as the display form doesn't carry the name type: an unusual
+    /// service on a single label host isn't recognized, and `alice/admin.example` is taken for
+    /// a service.
+    pub fn is_service(&self) -> bool {
+        const SERVICES: &[&str] = &[
+            "host", "HTTP", "ldap", "cifs", "nfs", "imap", "smtp", "postgres", "krbtgt", "kadmin",
+        ];
+        self.components.len() == 2
+            && (SERVICES.contains(&self.primary()) || self.components[1].contains('.'))
+    }
+
+    /// Compares the realm case-insensitively, as realms are conventionally upper case but
+    /// not always typed that way.
+    pub fn is_in_realm(&self, realm: &str) -> bool {
+        self.realm
+            .as_deref()
+            .is_some_and(|r| r.eq_ignore_ascii_case(realm))
+    }
+
+    /// Whether both name the same principal, comparing components exactly and the realm
+    /// case-insensitively. Principals without a realm never match.
+    pub(crate) fn is_same(&self, other: &Principal) -> bool {
+        self.components == other.components
+            && self.realm.as_deref().is_some_and(|r| other.is_in_realm(r))
+    }
+}
+
+fn escape(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    for c in s.chars() {
+        match c {
+            '/' | '@' | '\\' => write!(f, "\\{}", c)?,
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            '\x08' => write!(f, "\\b")?,
+            '\0' => write!(f, "\\0")?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for Principal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, c) in self.components.iter().enumerate() {
+            if i > 0 {
+                write!(f, "/")?;
+            }
+            escape(f, c)?;
+        }
+        if let Some(realm) = &self.realm {
+            write!(f, "@")?;
+            escape(f, realm)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Principal {
+    type Err = PrincipalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Principal::parse(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_components_and_realm() {
+        let p = Principal::parse("HTTP/www.example.com@EXAMPLE.COM").unwrap();
+        assert_eq!(p.components(), ["HTTP", "www.example.com"]);
+        assert_eq!(p.primary(), "HTTP");
+        assert_eq!(p.instance(), Some("www.example.com"));
+        assert_eq!(p.realm(), Some("EXAMPLE.COM"));
+
+        let p = Principal::parse("alice").unwrap();
+        assert_eq!(p.components(), ["alice"]);
+        assert_eq!(p.realm(), None);
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        assert_eq!(Principal::parse(""), Err(PrincipalError::Empty));
+        assert_eq!(Principal::parse("@EXAMPLE.COM"), Err(PrincipalError::Empty));
+        assert_eq!(Principal::parse("a@B@C"), Err(PrincipalError::MultipleRealms));
+        assert_eq!(Principal::parse("alice\\"), Err(PrincipalError::TrailingEscape));
+    }
+
+    #[test]
+    fn unescapes_rfc_1964_escapes() {
+        let p = Principal::parse(r"a\/b\@c\\d/e\n\t\b\0@R\@EALM").unwrap();
+        assert_eq!(p.components(), [r"a/b@c\d", "e\n\t\x08\0"]);
+        assert_eq!(p.realm(), Some("R@EALM"));
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for name in [
+            "alice@EXAMPLE.COM",
+            "alice/admin@EXAMPLE.COM",
+            "HTTP/www.example.com@EXAMPLE.COM",
+            "alice",
+            r"a\/b\@c\\d/e\n\t\b\0@R\@EALM",
+        ] {
+            let p = Principal::parse(name).unwrap();
+            assert_eq!(p.to_string(), name);
+            assert_eq!(p.to_string().parse::<Principal>(), Ok(p));
+        }
+        let p = Principal::new(vec!["a/b".into(), "c@d".into()], Some(r"E\F".into())).unwrap();
+        assert_eq!(p.to_string(), r"a\/b/c\@d@E\\F");
+        assert_eq!(Principal::parse(&p.to_string()), Ok(p));
+    }
+
+    #[test]
+    fn classifies_service_principals() {
+        let service = |name: &str| Principal::parse(name).unwrap().is_service();
+        assert!(service("HTTP/www.example.com@EXAMPLE.COM"));
+        assert!(service("host/localhost@EXAMPLE.COM"));
+        assert!(service("custom/db.example.com@EXAMPLE.COM"));
+        assert!(!service("alice/admin@EXAMPLE.COM"));
+        assert!(!service("alice@EXAMPLE.COM"));
+        assert!(!service("HTTP/www.example.com/extra@EXAMPLE.COM"));
+    }
+
+    #[test]
+    fn compares_realms_case_insensitively() {
+        let p = Principal::parse("alice@Example.Com").unwrap();
+        assert!(p.is_in_realm("EXAMPLE.COM"));
+        assert!(p.is_same(&Principal::parse("alice@EXAMPLE.COM").unwrap()));
+        assert!(!p.is_same(&Principal::parse("Alice@EXAMPLE.COM").unwrap()));
+        assert!(!Principal::parse("alice").unwrap().is_same(&Principal::parse("alice").unwrap()));
+    }
+}
@@ -0,0 +1,94 @@
+/// The client realms the fairing accepts principals from.
+///
+/// A pattern either names a realm exactly, or starts with `*.` to match any subrealm, e.g.
+/// `*.CORP.EXAMPLE` matches `EU.CORP.EXAMPLE` but not `CORP.EXAMPLE` itself. A lone `*`
+/// matches every realm. Realms are compared case-insensitively. An empty policy accepts every
+/// realm the KDC vouches for, including those reached through cross-realm trust.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RealmPolicy {
+    patterns: Vec<String>,
+}
+impl RealmPolicy {
+    pub fn new<I, S>(patterns: I) -> RealmPolicy
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        RealmPolicy {
+            patterns: patterns
+                .into_iter()
+                .map(|p| p.as_ref().to_ascii_uppercase())
+                .collect(),
+        }
+    }
+
+    /// Whether the policy restricts realms at all.
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Whether principals from `realm` are accepted.
+    pub fn allows(&self, realm: &str) -> bool {
+        if self.patterns.is_empty() {
+            return true;
+        }
+        let realm = realm.to_ascii_uppercase();
+        self.patterns.iter().any(|p| match p.strip_prefix('*') {
+            Some("") => true,
+            Some(suffix) if suffix.starts_with('.') => {
+                realm.len() > suffix.len() && realm.ends_with(suffix)
+            }
+            _ => *p == realm,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_policy_allows_everything() {
+        let policy = RealmPolicy::default();
+        assert!(policy.is_empty());
+        assert!(policy.allows("CORP.EXAMPLE"));
+        assert!(policy.allows(""));
+    }
+
+    #[test]
+    fn matches_exact_realms_case_insensitively() {
+        let policy = RealmPolicy::new(["corp.example"]);
+        assert!(policy.allows("CORP.EXAMPLE"));
+        assert!(policy.allows("Corp.Example"));
+        assert!(!policy.allows("EU.CORP.EXAMPLE"));
+        assert!(!policy.allows("CORP.EXAMPLE.EVIL"));
+    }
+
+    #[test]
+    fn matches_subrealm_wildcards() {
+        let policy = RealmPolicy::new(["*.CORP.EXAMPLE"]);
+        assert!(policy.allows("EU.CORP.EXAMPLE"));
+        assert!(policy.allows("a.eu.corp.example"));
+        assert!(!policy.allows("CORP.EXAMPLE"));
+        assert!(!policy.allows(".CORP.EXAMPLE"));
+        assert!(!policy.allows("EVILCORP.EXAMPLE"));
+
+        let policy = RealmPolicy::new(["CORP.EXAMPLE", "*.CORP.EXAMPLE"]);
+        assert!(policy.allows("CORP.EXAMPLE"));
+        assert!(policy.allows("EU.CORP.EXAMPLE"));
+    }
+
+    #[test]
+    fn lone_star_matches_every_realm() {
+        let policy = RealmPolicy::new(["*"]);
+        assert!(!policy.is_empty());
+        assert!(policy.allows("ANY.REALM"));
+    }
+
+    #[test]
+    fn other_patterns_match_literally() {
+        let policy = RealmPolicy::new(["*CORP.EXAMPLE"]);
+        assert!(!policy.allows("EVILCORP.EXAMPLE"));
+        assert!(policy.allows("*CORP.EXAMPLE"));
+    }
+}
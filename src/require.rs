@@ -0,0 +1,81 @@
+//! Support for the `#[gssapi_require]` attribute of the `macros` feature.
+use crate::error::GssapiError;
+use crate::group::is_member;
+use crate::guard::GssapiAuth;
+use crate::pac::Sid;
+use crate::principal::Principal;
+use crate::realm::RealmPolicy;
+use rocket::request::Outcome;
+use rocket::{info, Request};
+
+/// Requirements on the authenticated principal of a route, generated by `#[gssapi_require]`.
+///
+/// Each kind of requirement that is given must be met, by matching any of its values.
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy)]
+pub struct Requirements {
+    pub realms: &'static [&'static str],
+    pub principals: &'static [&'static str],
+    pub groups: &'static [&'static str],
+}
+impl Requirements {
+    /// Authenticates the request with the `GssapiAuth` guard, then checks the requirements,
+    /// failing with `403 Forbidden` if they are not met.
+    pub async fn check(&self, req: &Request<'_>) -> Outcome<GssapiAuth, GssapiError> {
+        let auth = match req.guard::<GssapiAuth>().await {
+            Outcome::Success(a) => a,
+            Outcome::Error(e) => return Outcome::Error(e),
+            Outcome::Forward(s) => return Outcome::Forward(s),
+        };
+
+        let mut allowed = self.allows(auth.principal.as_ref());
+        if allowed {
+            allowed = match self.in_groups(req, &auth).await {
+                Ok(ok) => ok,
+                Err(e) => return Outcome::Error((e.status(), e)),
+            };
+        }
+        if allowed {
+            Outcome::Success(auth)
+        } else {
+            let source = auth.source.clone().unwrap_or_default();
+            info!("Kerberos: {} does not meet the requirements of {}", source, req.uri());
+            let e = GssapiError::Forbidden(source);
+            Outcome::Error((e.status(), e))
+        }
+    }
+
+    /// Groups given as SIDs are matched against the PAC, and all groups are passed to the
+    /// group resolver otherwise.
+    async fn in_groups(&self, req: &Request<'_>, auth: &GssapiAuth) -> Result<bool, GssapiError> {
+        if self.groups.is_empty() {
+            return Ok(true);
+        }
+        for group in self.groups {
+            let sid = group.parse::<Sid>().ok();
+            if is_member(req, auth, group, sid.as_ref()).await? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn allows(&self, principal: Option<&Principal>) -> bool {
+        let principal = if let Some(p) = principal {
+            p
+        } else {
+            return false;
+        };
+
+        let realm_ok = self.realms.is_empty()
+            || principal
+                .realm()
+                .is_some_and(|r| RealmPolicy::new(self.realms).allows(r));
+        let principal_ok = self.principals.is_empty()
+            || self
+                .principals
+                .iter()
+                .any(|p| Principal::parse(p).is_ok_and(|p| p.is_same(principal)));
+        realm_ok && principal_ok
+    }
+}
@@ -0,0 +1,433 @@
+//! Decoder for SPNEGO negotiation tokens as described in RFC 4178, for diagnostics.
+use crate::der;
+use crate::token::TokenKind;
+use std::fmt;
+
+/// The DER value of the SPNEGO mechanism OID, `1.3.6.1.5.5.2`.
+pub(crate) const SPNEGO_OID: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x02];
+
+/// An OBJECT IDENTIFIER, such as the OID of an offered mechanism.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ObjectId {
+    arcs: Vec<u64>,
+}
+impl ObjectId {
+    pub fn from_arcs(arcs: Vec<u64>) -> ObjectId {
+        ObjectId { arcs }
+    }
+
+    pub fn arcs(&self) -> &[u64] {
+        &self.arcs
+    }
+
+    /// The common name of well-known mechanisms, e.g. `krb5`.
+    pub fn name(&self) -> Option<&'static str> {
+        match self.arcs.as_slice() {
+            [1, 2, 840, 113554, 1, 2, 2] => Some("krb5"),
+            [1, 2, 840, 48018, 1, 2, 2] => Some("ms-krb5"),
+            [1, 3, 6, 1, 5, 2, 5] => Some("iakerb"),
+            [1, 3, 6, 1, 5, 5, 2] => Some("spnego"),
+            [1, 3, 6, 1, 4, 1, 311, 2, 2, 10] => Some("ntlmssp"),
+            [1, 3, 6, 1, 4, 1, 311, 2, 2, 30] => Some("negoex"),
+            _ => None,
+        }
+    }
+
+    fn decode(value: &[u8]) -> Result<ObjectId, SpnegoError> {
+        der::oid_arcs(value)
+            .map(ObjectId::from_arcs)
+            .ok_or(SpnegoError::Malformed)
+    }
+
+    /// The name of well-known mechanisms, the dotted form otherwise.
+    fn describe(&self) -> String {
+        self.name().map_or_else(|| self.to_string(), str::to_string)
+    }
+}
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, arc) in self.arcs.iter().enumerate() {
+            if i > 0 {
+                write!(f, ".")?;
+            }
+            write!(f, "{}", arc)?;
+        }
+        Ok(())
+    }
+}
+
+/// The state of a negotiation, as reported by the acceptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NegState {
+    AcceptCompleted,
+    AcceptIncomplete,
+    Reject,
+    RequestMic,
+}
+impl fmt::Display for NegState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            NegState::AcceptCompleted => "accept-completed",
+            NegState::AcceptIncomplete => "accept-incomplete",
+            NegState::Reject => "reject",
+            NegState::RequestMic => "request-mic",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The first token of a negotiation, sent by the initiator.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NegTokenInit {
+    /// The offered mechanisms, the preferred one first.
+    pub mech_types: Vec<ObjectId>,
+    /// The optimistic token of the preferred mechanism.
+    pub mech_token: Option<Vec<u8>>,
+    pub mech_list_mic: Option<Vec<u8>>,
+}
+impl NegTokenInit {
+    /// The kind of the optimistic mechanism token, if there is one.
+    pub fn mech_token_kind(&self) -> Option<TokenKind> {
+        self.mech_token.as_deref().map(TokenKind::of)
+    }
+}
+
+/// Any later token of a negotiation, sent by either side.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NegTokenResp {
+    pub neg_state: Option<NegState>,
+    /// The mechanism chosen by the acceptor, in its first response.
+    pub supported_mech: Option<ObjectId>,
+    pub response_token: Option<Vec<u8>>,
+    pub mech_list_mic: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NegToken {
+    Init(NegTokenInit),
+    Resp(NegTokenResp),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpnegoError {
+    /// The token is not a SPNEGO token.
+    NotSpnego,
+    /// The token claims to be a SPNEGO token, but doesn't decode as one.
+    Malformed,
+}
+impl fmt::Display for SpnegoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpnegoError::NotSpnego => write!(f, "not a SPNEGO token"),
+            SpnegoError::Malformed => write!(f, "malformed SPNEGO token"),
+        }
+    }
+}
+impl std::error::Error for SpnegoError {}
+
+impl NegToken {
+    /// Decodes a SPNEGO token, either the initial one with its GSSAPI header or a later
+    /// `NegTokenResp`.
+    pub fn parse(token: &[u8]) -> Result<NegToken, SpnegoError> {
+        let choice = match der::read(token) {
+            Some((t, _)) if t.tag == 0x60 => {
+                let (oid, rest) = der::expect(t.value, 0x06).ok_or(SpnegoError::NotSpnego)?;
+                if oid != SPNEGO_OID {
+                    return Err(SpnegoError::NotSpnego);
+                }
+                rest
+            }
+            Some((t, _)) if t.tag == 0xa1 => token,
+            _ => return Err(SpnegoError::NotSpnego),
+        };
+
+        match der::read(choice).ok_or(SpnegoError::Malformed)? {
+            (t, _) if t.tag == 0xa0 => NegToken::parse_init(t.value).map(NegToken::Init),
+            (t, _) if t.tag == 0xa1 => NegToken::parse_resp(t.value).map(NegToken::Resp),
+            _ => Err(SpnegoError::Malformed),
+        }
+    }
+
+    fn parse_init(value: &[u8]) -> Result<NegTokenInit, SpnegoError> {
+        let (mut fields, _) = der::expect(value, 0x30).ok_or(SpnegoError::Malformed)?;
+        let mut init = NegTokenInit::default();
+        while !fields.is_empty() {
+            let (field, rest) = der::read(fields).ok_or(SpnegoError::Malformed)?;
+            fields = rest;
+            match field.tag {
+                0xa0 => {
+                    let (mut types, _) =
+                        der::expect(field.value, 0x30).ok_or(SpnegoError::Malformed)?;
+                    while !types.is_empty() {
+                        let (oid, rest) = der::expect(types, 0x06).ok_or(SpnegoError::Malformed)?;
+                        init.mech_types.push(ObjectId::decode(oid)?);
+                        types = rest;
+                    }
+                }
+                0xa2 => init.mech_token = Some(octet_string(field.value)?),
+                // Windows sends the NegTokenInit2 variant with negHints in [3] and the MIC
+                // in [4]
+                0xa3 | 0xa4 => {
+                    if let Some((mic, _)) = der::expect(field.value, 0x04) {
+                        init.mech_list_mic = Some(mic.to_vec());
+                    }
+                }
+                // reqFlags, which are deprecated
+                _ => {}
+            }
+        }
+        Ok(init)
+    }
+
+    fn parse_resp(value: &[u8]) -> Result<NegTokenResp, SpnegoError> {
+        let (mut fields, _) = der::expect(value, 0x30).ok_or(SpnegoError::Malformed)?;
+        let mut resp = NegTokenResp::default();
+        while !fields.is_empty() {
+            let (field, rest) = der::read(fields).ok_or(SpnegoError::Malformed)?;
+            fields = rest;
+            match field.tag {
+                0xa0 => {
+                    let (state, _) =
+                        der::expect(field.value, 0x0a).ok_or(SpnegoError::Malformed)?;
+                    resp.neg_state = Some(match state {
+                        [0] => NegState::AcceptCompleted,
+                        [1] => NegState::AcceptIncomplete,
+                        [2] => NegState::Reject,
+                        [3] => NegState::RequestMic,
+                        _ => return Err(SpnegoError::Malformed),
+                    });
+                }
+                0xa1 => {
+                    let (oid, _) = der::expect(field.value, 0x06).ok_or(SpnegoError::Malformed)?;
+                    resp.supported_mech = Some(ObjectId::decode(oid)?);
+                }
+                0xa2 => resp.response_token = Some(octet_string(field.value)?),
+                0xa3 => resp.mech_list_mic = Some(octet_string(field.value)?),
+                _ => {}
+            }
+        }
+        Ok(resp)
+    }
+}
+
+fn octet_string(value: &[u8]) -> Result<Vec<u8>, SpnegoError> {
+    der::expect(value, 0x04)
+        .map(|(v, _)| v.to_vec())
+        .ok_or(SpnegoError::Malformed)
+}
+
+/// Summarizes the token for logging, e.g. `NegTokenInit offering krb5, ntlmssp with an
+/// optimistic Kerberos token`.
+impl fmt::Display for NegToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NegToken::Init(init) => {
+                let mechs: Vec<String> = init.mech_types.iter().map(ObjectId::describe).collect();
+                write!(f, "NegTokenInit offering {}", mechs.join(", "))?;
+                match init.mech_token_kind() {
+                    Some(kind) => write!(f, " with an optimistic {} token", kind)?,
+                    None => write!(f, " without a token")?,
+                }
+                if init.mech_list_mic.is_some() {
+                    write!(f, " and a MIC")?;
+                }
+                Ok(())
+            }
+            NegToken::Resp(resp) => {
+                write!(f, "NegTokenResp")?;
+                if let Some(state) = resp.neg_state {
+                    write!(f, " {}", state)?;
+                }
+                if let Some(mech) = &resp.supported_mech {
+                    write!(f, " for {}", mech.describe())?;
+                }
+                if let Some(token) = &resp.response_token {
+                    write!(f, " with a {} byte token", token.len())?;
+                }
+                if resp.mech_list_mic.is_some() {
+                    write!(f, " and a MIC")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::der::tlv;
+    use crate::token::{KRB5_OID, MS_KRB5_OID, NTLMSSP_OID};
+
+    /// The response of IIS completing a Kerberos negotiation without a mutual authentication
+    /// token, `oRQwEqADCgEAoQsGCSqGSIb3EgECAg==`.
+    const IIS_ACCEPT_COMPLETED: &[u8] = &[
+        0xa1, 0x14, 0x30, 0x12, 0xa0, 0x03, 0x0a, 0x01, 0x00, 0xa1, 0x0b, 0x06, 0x09, 0x2a, 0x86,
+        0x48, 0x86, 0xf7, 0x12, 0x01, 0x02, 0x02,
+    ];
+
+    fn mech_types(mechs: &[&[u8]]) -> Vec<u8> {
+        let oids: Vec<u8> = mechs.iter().flat_map(|m| tlv(0x06, m)).collect();
+        tlv(0xa0, &tlv(0x30, &oids))
+    }
+
+    /// Frames the fields of a `NegTokenInit` in the GSSAPI header of the initial token.
+    fn initial_token(fields: &[u8]) -> Vec<u8> {
+        let init = tlv(0xa0, &tlv(0x30, fields));
+        tlv(0x60, &[tlv(0x06, SPNEGO_OID), init].concat())
+    }
+
+    /// An AP-REQ in its GSSAPI framing, padded to `len` bytes of ticket.
+    fn krb5_token(len: usize) -> Vec<u8> {
+        let ap_req = tlv(0x6e, &tlv(0x30, &vec![0; len]));
+        tlv(
+            0x60,
+            &[tlv(0x06, KRB5_OID), vec![0x01, 0x00], ap_req].concat(),
+        )
+    }
+
+    #[test]
+    fn parses_neg_token_init() {
+        let krb5 = krb5_token(1500);
+        let fields = [
+            mech_types(&[MS_KRB5_OID, KRB5_OID, NTLMSSP_OID]),
+            tlv(0xa2, &tlv(0x04, &krb5)),
+        ]
+        .concat();
+        let token = initial_token(&fields);
+        // The ticket makes every enclosing length long-form
+        assert_eq!(token[..2], [0x60, 0x82]);
+
+        let init = match NegToken::parse(&token).unwrap() {
+            NegToken::Init(i) => i,
+            t => panic!("unexpected {:?}", t),
+        };
+        let names: Vec<_> = init.mech_types.iter().map(ObjectId::name).collect();
+        assert_eq!(names, [Some("ms-krb5"), Some("krb5"), Some("ntlmssp")]);
+        assert_eq!(init.mech_token.as_deref(), Some(krb5.as_slice()));
+        assert_eq!(init.mech_token_kind(), Some(TokenKind::Kerberos));
+        assert_eq!(init.mech_list_mic, None);
+        assert_eq!(
+            NegToken::Init(init).to_string(),
+            "NegTokenInit offering ms-krb5, krb5, ntlmssp with an optimistic Kerberos token"
+        );
+    }
+
+    #[test]
+    fn parses_neg_token_init2() {
+        // As sent by Windows, with the negHints of [MS-SPNG] 2.2.1 and a MIC in [4]
+        let hints = tlv(
+            0x30,
+            &tlv(0xa0, &tlv(0x1b, b"not_defined_in_RFC4178@please_ignore")),
+        );
+        let fields = [
+            mech_types(&[KRB5_OID, &[0x2a, 0x03, 0x04]]),
+            tlv(0xa3, &hints),
+            tlv(0xa4, &tlv(0x04, &[0x01, 0x02, 0x03, 0x04])),
+        ]
+        .concat();
+        let token = NegToken::parse(&initial_token(&fields)).unwrap();
+        let init = match &token {
+            NegToken::Init(i) => i,
+            t => panic!("unexpected {:?}", t),
+        };
+        assert_eq!(init.mech_types[1].arcs(), [1, 2, 3, 4]);
+        assert_eq!(init.mech_token, None);
+        assert_eq!(init.mech_list_mic.as_deref(), Some(&[1, 2, 3, 4][..]));
+        assert_eq!(
+            token.to_string(),
+            "NegTokenInit offering krb5, 1.2.3.4 without a token and a MIC"
+        );
+    }
+
+    #[test]
+    fn parses_neg_token_resp() {
+        let resp = match NegToken::parse(IIS_ACCEPT_COMPLETED).unwrap() {
+            NegToken::Resp(r) => r,
+            t => panic!("unexpected {:?}", t),
+        };
+        assert_eq!(resp.neg_state, Some(NegState::AcceptCompleted));
+        assert_eq!(
+            resp.supported_mech.as_ref().and_then(ObjectId::name),
+            Some("krb5")
+        );
+        assert_eq!(resp.response_token, None);
+        assert_eq!(
+            NegToken::Resp(resp).to_string(),
+            "NegTokenResp accept-completed for krb5"
+        );
+
+        let fields = [
+            tlv(0xa0, &tlv(0x0a, &[1])),
+            tlv(0xa2, &tlv(0x04, &[0xaa; 200])),
+            tlv(0xa3, &tlv(0x04, &[0xbb; 16])),
+        ]
+        .concat();
+        let token = NegToken::parse(&tlv(0xa1, &tlv(0x30, &fields))).unwrap();
+        assert_eq!(
+            token.to_string(),
+            "NegTokenResp accept-incomplete with a 200 byte token and a MIC"
+        );
+    }
+
+    #[test]
+    fn rejects_other_tokens() {
+        assert_eq!(NegToken::parse(&[]), Err(SpnegoError::NotSpnego));
+        assert_eq!(
+            NegToken::parse(b"NTLMSSP\0\x01\0\0\0"),
+            Err(SpnegoError::NotSpnego)
+        );
+        assert_eq!(
+            NegToken::parse(&krb5_token(16)),
+            Err(SpnegoError::NotSpnego)
+        );
+        assert_eq!(
+            NegToken::parse(&tlv(0x6e, &[])),
+            Err(SpnegoError::NotSpnego)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_tokens() {
+        let malformed = |token: &[u8]| NegToken::parse(token) == Err(SpnegoError::Malformed);
+        // The SPNEGO OID followed by neither choice
+        assert!(malformed(&tlv(
+            0x60,
+            &[tlv(0x06, SPNEGO_OID), tlv(0xa5, &[])].concat()
+        )));
+        assert!(malformed(&tlv(0x60, &tlv(0x06, SPNEGO_OID))));
+        // A field that isn't a sequence
+        assert!(malformed(&tlv(0xa1, &tlv(0x04, &[]))));
+        // An unknown negState
+        assert!(malformed(&tlv(
+            0xa1,
+            &tlv(0x30, &tlv(0xa0, &tlv(0x0a, &[7])))
+        )));
+        // A mechanism that isn't an OID, or an OID cut off in a subidentifier
+        assert!(malformed(&initial_token(&tlv(
+            0xa0,
+            &tlv(0x30, &tlv(0x04, &[]))
+        ))));
+        assert!(malformed(&initial_token(&mech_types(&[&[0x2a, 0x86]]))));
+        // A mechToken that isn't an OCTET STRING
+        assert!(malformed(&initial_token(&tlv(0xa2, &tlv(0x30, &[])))));
+        // A sequence running past the end of its field
+        let mut token = initial_token(&mech_types(&[KRB5_OID]));
+        let len = token.len();
+        token[len - 12] += 1;
+        assert!(malformed(&token));
+    }
+
+    #[test]
+    fn rejects_truncated_tokens() {
+        let fields = [
+            mech_types(&[KRB5_OID]),
+            tlv(0xa2, &tlv(0x04, &krb5_token(300))),
+        ]
+        .concat();
+        let token = initial_token(&fields);
+        for len in 0..token.len() {
+            assert!(NegToken::parse(&token[..len]).is_err(), "{}", len);
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use crate::accept::AcceptCtx;
+use rocket::{error, warn};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Limits the fairing places on half-finished contexts. These are passed to the
+/// [`ContextStore`] on every insert and sweep, a store may choose to enforce its own policy
+/// instead.
+#[derive(Debug, Clone, Copy)]
+pub struct ContextLimits {
+    /// How long a context is kept waiting for the client to send the next token.
+    pub idle_timeout: Duration,
+    /// The maximum time a negotiation may take from the first token to completion.
+    pub negotiation_timeout: Duration,
+    /// The maximum number of contexts kept at once.
+    pub max_contexts: usize,
+    /// The maximum number of concurrent negotiations from a single client IP address.
+    pub max_contexts_per_client: usize,
+}
+impl Default for ContextLimits {
+    fn default() -> Self {
+        ContextLimits {
+            idle_timeout: Duration::from_secs(30),
+            negotiation_timeout: Duration::from_secs(120),
+            max_contexts: 1024,
+            max_contexts_per_client: 4,
+        }
+    }
+}
+
+/// A context waiting for the next leg of the negotiation from a client.
+pub struct PendingContext {
+    pub ctx: AcceptCtx,
+    pub peer: Option<IpAddr>,
+    pub started: Instant,
+    pub last_seen: Instant,
+}
+impl PendingContext {
+    pub fn new(ctx: AcceptCtx, peer: Option<IpAddr>) -> PendingContext {
+        let now = Instant::now();
+        PendingContext {
+            ctx,
+            peer,
+            started: now,
+            last_seen: now,
+        }
+    }
+
+    /// A context is stale when the client has not continued the negotiation within
+    /// `idle_timeout`, or when the negotiation as a whole has taken longer than
+    /// `negotiation_timeout`.
+    pub fn is_stale(&self, now: Instant, limits: &ContextLimits) -> bool {
+        now.duration_since(self.last_seen) > limits.idle_timeout
+            || now.duration_since(self.started) > limits.negotiation_timeout
+    }
+}
+
+/// Storage for contexts that are waiting for the client to continue the negotiation.
+///
+/// Contexts are keyed by the client identifier of the fairing. A context is taken out of the
+/// store while a token is being worked, and inserted back if the negotiation needs another leg.
+pub trait ContextStore: Send + Sync {
+    /// Stores a context under `key`, replacing any previous context. Returns false if the
+    /// store refuses to keep the context, in which case it is dropped.
+    fn insert(&self, key: String, ctx: PendingContext, limits: &ContextLimits) -> bool;
+
+    /// Removes and returns the context stored under `key`.
+    fn take(&self, key: &str) -> Option<PendingContext>;
+
+    /// Removes the context stored under `key`, returning whether there was one.
+    fn remove(&self, key: &str) -> bool;
+
+    /// Removes all stale contexts, returning the number of contexts removed.
+    fn sweep(&self, limits: &ContextLimits) -> usize;
+
+    /// Returns the number of stored contexts.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The default [`ContextStore`], keeping contexts in a `HashMap` behind a `Mutex`.
+///
+/// Enforces the [`ContextLimits`] passed by the fairing, evicting the least recently used
+/// contexts when full and refusing contexts from clients with too many negotiations in progress.
+#[derive(Default)]
+pub struct MemoryStore {
+    contexts: Mutex<HashMap<String, PendingContext>>,
+}
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+impl ContextStore for MemoryStore {
+    fn insert(&self, key: String, ctx: PendingContext, limits: &ContextLimits) -> bool {
+        let mut contexts = if let Ok(c) = self.contexts.lock() {
+            c
+        } else {
+            error!("Kerberos: Failed to lock context store");
+            return false;
+        };
+        contexts.remove(&key);
+
+        if ctx.peer.is_some()
+            && contexts.values().filter(|p| p.peer == ctx.peer).count()
+                >= limits.max_contexts_per_client
+        {
+            return false;
+        }
+
+        let mut shed = 0;
+        while !contexts.is_empty() && contexts.len() >= limits.max_contexts {
+            let lru = contexts
+                .iter()
+                .min_by_key(|(_, p)| p.last_seen)
+                .map(|(k, _)| k.clone());
+            if let Some(lru) = lru {
+                contexts.remove(&lru);
+                shed += 1;
+            }
+        }
+        if shed > 0 {
+            warn!(
+                "Kerberos: Context store full ({} contexts), shed {} least recently used",
+                limits.max_contexts, shed
+            );
+        }
+
+        contexts.insert(key, ctx);
+        true
+    }
+
+    fn take(&self, key: &str) -> Option<PendingContext> {
+        self.contexts.lock().ok()?.remove(key)
+    }
+
+    fn remove(&self, key: &str) -> bool {
+        self.take(key).is_some()
+    }
+
+    fn sweep(&self, limits: &ContextLimits) -> usize {
+        let mut contexts = if let Ok(c) = self.contexts.lock() {
+            c
+        } else {
+            error!("Kerberos: Failed to lock context store");
+            return 0;
+        };
+        let now = Instant::now();
+        let before = contexts.len();
+        contexts.retain(|_, p| !p.is_stale(now, limits));
+        before - contexts.len()
+    }
+
+    fn len(&self) -> usize {
+        self.contexts.lock().map_or(0, |c| c.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending(peer: Option<IpAddr>, idle: u64, age: u64) -> PendingContext {
+        let now = Instant::now();
+        let mut p = PendingContext::new(AcceptCtx::new(None), peer);
+        p.last_seen = now - Duration::from_secs(idle);
+        p.started = now - Duration::from_secs(age);
+        p
+    }
+
+    #[test]
+    fn stale_after_idle_or_negotiation_timeout() {
+        let limits = ContextLimits::default();
+        let now = Instant::now();
+        assert!(!pending(None, 0, 0).is_stale(now, &limits));
+        assert!(!pending(None, 29, 100).is_stale(now, &limits));
+        assert!(pending(None, 31, 31).is_stale(now, &limits));
+        assert!(pending(None, 0, 121).is_stale(now, &limits));
+    }
+
+    #[test]
+    fn sweep_removes_only_stale_contexts() {
+        let store = MemoryStore::new();
+        let limits = ContextLimits::default();
+        assert!(store.insert("fresh".into(), pending(None, 1, 1), &limits));
+        assert!(store.insert("idle".into(), pending(None, 60, 60), &limits));
+        assert!(store.insert("slow".into(), pending(None, 1, 600), &limits));
+
+        assert_eq!(store.sweep(&limits), 2);
+        assert_eq!(store.len(), 1);
+        assert!(store.take("fresh").is_some());
+        assert_eq!(store.sweep(&limits), 0);
+    }
+
+    #[test]
+    fn evicts_least_recently_used_when_full() {
+        let store = MemoryStore::new();
+        let limits = ContextLimits {
+            max_contexts: 2,
+            ..ContextLimits::default()
+        };
+        assert!(store.insert("old".into(), pending(None, 10, 10), &limits));
+        assert!(store.insert("new".into(), pending(None, 1, 1), &limits));
+        assert!(store.insert("newest".into(), pending(None, 0, 0), &limits));
+
+        assert_eq!(store.len(), 2);
+        assert!(store.take("old").is_none());
+        assert!(store.take("new").is_some());
+        assert!(store.take("newest").is_some());
+    }
+
+    #[test]
+    fn caps_contexts_per_client() {
+        let store = MemoryStore::new();
+        let limits = ContextLimits {
+            max_contexts_per_client: 2,
+            ..ContextLimits::default()
+        };
+        let a = Some(IpAddr::from([192, 0, 2, 1]));
+        let b = Some(IpAddr::from([192, 0, 2, 2]));
+        assert!(store.insert("a1".into(), pending(a, 0, 0), &limits));
+        assert!(store.insert("a2".into(), pending(a, 0, 0), &limits));
+        assert!(!store.insert("a3".into(), pending(a, 0, 0), &limits));
+        assert!(store.insert("b1".into(), pending(b, 0, 0), &limits));
+
+        // Continuing a negotiation replaces the client's own context
+        assert!(store.insert("a2".into(), pending(a, 0, 0), &limits));
+        // Contexts without a known peer aren't capped
+        for i in 0..4 {
+            assert!(store.insert(format!("none{}", i), pending(None, 0, 0), &limits));
+        }
+        assert_eq!(store.len(), 7);
+    }
+
+    #[test]
+    fn works_as_a_trait_object() {
+        let store: Box<dyn ContextStore> = Box::new(MemoryStore::new());
+        let limits = ContextLimits::default();
+        assert!(store.is_empty());
+        assert!(store.insert("a".into(), pending(None, 0, 0), &limits));
+        assert!(!store.is_empty());
+
+        assert!(store.take("a").is_some());
+        assert!(store.take("a").is_none());
+        assert!(store.insert("a".into(), pending(None, 0, 0), &limits));
+        assert!(store.remove("a"));
+        assert!(!store.remove("a"));
+        assert!(store.is_empty());
+    }
+}
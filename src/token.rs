@@ -0,0 +1,217 @@
+//! Classification of the tokens clients send in `Authorization: Negotiate`.
+use crate::der;
+use crate::spnego::SPNEGO_OID;
+use rocket::serde::Deserialize;
+use std::fmt;
+
+/// `1.2.840.113554.1.2.2`
+pub(crate) const KRB5_OID: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x12, 0x01, 0x02, 0x02];
+/// `1.2.840.48018.1.2.2`, the Kerberos OID with a typo sent by old Windows clients
+pub(crate) const MS_KRB5_OID: &[u8] = &[0x2a, 0x86, 0x48, 0x82, 0xf7, 0x12, 0x01, 0x02, 0x02];
+/// `1.3.6.1.5.2.5`
+const IAKERB_OID: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x02, 0x05];
+/// `1.3.6.1.4.1.311.2.2.10`
+pub(crate) const NTLMSSP_OID: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0x37, 0x02, 0x02, 0x0a];
+
+/// What a client token contains, judging by its framing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// A SPNEGO token, as RFC 4559 prescribes.
+    Spnego,
+    /// A Kerberos or IAKERB token with a GSSAPI header, but not wrapped in SPNEGO.
+    Kerberos,
+    /// A bare Kerberos AP-REQ without a GSSAPI header.
+    ApReq,
+    /// An NTLMSSP message, bare or preferred in a SPNEGO `NegTokenInit`, sent by Windows
+    /// clients that couldn't get a ticket.
+    Ntlm,
+    /// Anything else.
+    Unknown,
+}
+impl TokenKind {
+    pub fn of(token: &[u8]) -> TokenKind {
+        if token.starts_with(b"NTLMSSP\0") {
+            return TokenKind::Ntlm;
+        }
+        match der::read(token) {
+            // The InitialContextToken of RFC 2743, an OID followed by the mechanism token
+            Some((t, _)) if t.tag == 0x60 => match der::read(t.value) {
+                Some((oid, rest)) if oid.tag == 0x06 => match oid.value {
+                    SPNEGO_OID if prefers_ntlm(rest) => TokenKind::Ntlm,
+                    SPNEGO_OID => TokenKind::Spnego,
+                    KRB5_OID | MS_KRB5_OID | IAKERB_OID => TokenKind::Kerberos,
+                    _ => TokenKind::Unknown,
+                },
+                _ => TokenKind::Unknown,
+            },
+            // A NegTokenResp, continuing a SPNEGO negotiation
+            Some((t, _)) if t.tag == 0xa1 => TokenKind::Spnego,
+            Some((t, _)) if t.tag == 0x6e => TokenKind::ApReq,
+            _ => TokenKind::Unknown,
+        }
+    }
+}
+
+/// Whether a `NegTokenInit` prefers NTLMSSP, as sent by Windows clients that fall back to NTLM
+/// within SPNEGO: either the first offered mechanism or the optimistic token is NTLMSSP.
+fn prefers_ntlm(init: &[u8]) -> bool {
+    let mut fields = match der::expect(init, 0xa0).and_then(|(v, _)| der::expect(v, 0x30)) {
+        Some((fields, _)) => fields,
+        None => return false,
+    };
+    while let Some((field, rest)) = der::read(fields) {
+        fields = rest;
+        let ntlm = match field.tag {
+            0xa0 => der::expect(field.value, 0x30)
+                .and_then(|(types, _)| der::expect(types, 0x06))
+                .is_some_and(|(oid, _)| oid == NTLMSSP_OID),
+            0xa2 => der::expect(field.value, 0x04)
+                .is_some_and(|(token, _)| token.starts_with(b"NTLMSSP\0")),
+            _ => false,
+        };
+        if ntlm {
+            return true;
+        }
+    }
+    false
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TokenKind::Spnego => "SPNEGO",
+            TokenKind::Kerberos => "Kerberos",
+            TokenKind::ApReq => "bare Kerberos AP-REQ",
+            TokenKind::Ntlm => "NTLM",
+            TokenKind::Unknown => "unknown",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What to do with a kind of token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum TokenAction {
+    /// Fail with [`GssapiError::UnsupportedToken`](crate::GssapiError::UnsupportedToken)
+    /// without passing the token to GSSAPI.
+    Reject,
+    /// Pass the token to GSSAPI, for when a mechanism supporting it is installed, such as
+    /// gss-ntlmssp for NTLM.
+    PassThrough,
+}
+
+/// Which tokens are passed to GSSAPI, by their [`TokenKind`]. SPNEGO tokens always are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenPolicy {
+    /// NTLMSSP messages. Rejected by default, as MIT Kerberos can't accept them on its own.
+    pub ntlm: TokenAction,
+    /// Kerberos tokens not wrapped in SPNEGO, with or without a GSSAPI header. Passed through
+    /// by default.
+    pub raw_kerberos: TokenAction,
+    /// Tokens of no known kind. Passed through by default.
+    pub unknown: TokenAction,
+}
+impl Default for TokenPolicy {
+    fn default() -> Self {
+        TokenPolicy {
+            ntlm: TokenAction::Reject,
+            raw_kerberos: TokenAction::PassThrough,
+            unknown: TokenAction::PassThrough,
+        }
+    }
+}
+impl TokenPolicy {
+    pub fn action(&self, kind: TokenKind) -> TokenAction {
+        match kind {
+            TokenKind::Spnego => TokenAction::PassThrough,
+            TokenKind::Kerberos | TokenKind::ApReq => self.raw_kerberos,
+            TokenKind::Ntlm => self.ntlm,
+            TokenKind::Unknown => self.unknown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::der::tlv;
+
+    const NTLM_NEGOTIATE: &[u8] = b"NTLMSSP\0\x01\0\0\0\x97\x82\x08\xe2";
+
+    /// A `NegTokenInit` offering `mechs` with an optimistic `token`, in its GSSAPI framing.
+    fn neg_token_init(mechs: &[&[u8]], token: &[u8]) -> Vec<u8> {
+        let oids: Vec<u8> = mechs.iter().flat_map(|m| tlv(0x06, m)).collect();
+        let mut fields = tlv(0xa0, &tlv(0x30, &oids));
+        fields.extend(tlv(0xa2, &tlv(0x04, token)));
+        let init = tlv(0xa0, &tlv(0x30, &fields));
+        tlv(0x60, &[tlv(0x06, SPNEGO_OID), init].concat())
+    }
+
+    fn krb5_token() -> Vec<u8> {
+        let ap_req = tlv(0x6e, &tlv(0x30, &[]));
+        tlv(
+            0x60,
+            &[tlv(0x06, KRB5_OID), vec![0x01, 0x00], ap_req].concat(),
+        )
+    }
+
+    #[test]
+    fn classifies_tokens() {
+        assert_eq!(TokenKind::of(&krb5_token()), TokenKind::Kerberos);
+        let ms_krb5 = tlv(0x60, &[tlv(0x06, MS_KRB5_OID), vec![0x01, 0x00]].concat());
+        assert_eq!(TokenKind::of(&ms_krb5), TokenKind::Kerberos);
+        let iakerb = tlv(0x60, &[tlv(0x06, IAKERB_OID), vec![0x05, 0x01]].concat());
+        assert_eq!(TokenKind::of(&iakerb), TokenKind::Kerberos);
+        assert_eq!(TokenKind::of(&tlv(0x6e, &tlv(0x30, &[]))), TokenKind::ApReq);
+        assert_eq!(TokenKind::of(NTLM_NEGOTIATE), TokenKind::Ntlm);
+
+        let init = neg_token_init(&[KRB5_OID, NTLMSSP_OID], &krb5_token());
+        assert_eq!(TokenKind::of(&init), TokenKind::Spnego);
+        // The NegTokenResp of a later leg, accept-incomplete
+        let resp = tlv(0xa1, &tlv(0x30, &tlv(0xa0, &tlv(0x0a, &[1]))));
+        assert_eq!(TokenKind::of(&resp), TokenKind::Spnego);
+    }
+
+    #[test]
+    fn classifies_unknown_tokens() {
+        assert_eq!(TokenKind::of(&[]), TokenKind::Unknown);
+        assert_eq!(TokenKind::of(b"garbage"), TokenKind::Unknown);
+        let other = tlv(0x60, &tlv(0x06, &[0x2a, 0x03]));
+        assert_eq!(TokenKind::of(&other), TokenKind::Unknown);
+        // Truncated GSSAPI framing
+        let truncated = krb5_token();
+        assert_eq!(
+            TokenKind::of(&truncated[..truncated.len() - 1]),
+            TokenKind::Unknown
+        );
+    }
+
+    #[test]
+    fn detects_ntlm_in_spnego() {
+        // Windows falling back to NTLM after failing to get a ticket
+        let init = neg_token_init(&[NTLMSSP_OID], NTLM_NEGOTIATE);
+        assert_eq!(TokenKind::of(&init), TokenKind::Ntlm);
+        // NTLMSSP preferred, but with NEGOEX offered as well
+        let negoex = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0x37, 0x02, 0x02, 0x1e];
+        let init = neg_token_init(&[NTLMSSP_OID, negoex], NTLM_NEGOTIATE);
+        assert_eq!(TokenKind::of(&init), TokenKind::Ntlm);
+        // An NTLMSSP token although Kerberos is listed first
+        let init = neg_token_init(&[KRB5_OID, NTLMSSP_OID], NTLM_NEGOTIATE);
+        assert_eq!(TokenKind::of(&init), TokenKind::Ntlm);
+    }
+
+    #[test]
+    fn applies_token_policy() {
+        let policy = TokenPolicy::default();
+        assert_eq!(policy.action(TokenKind::Spnego), TokenAction::PassThrough);
+        assert_eq!(policy.action(TokenKind::Ntlm), TokenAction::Reject);
+        assert_eq!(policy.action(TokenKind::ApReq), TokenAction::PassThrough);
+        let policy = TokenPolicy {
+            raw_kerberos: TokenAction::Reject,
+            ..policy
+        };
+        assert_eq!(policy.action(TokenKind::Kerberos), TokenAction::Reject);
+        assert_eq!(policy.action(TokenKind::Unknown), TokenAction::PassThrough);
+    }
+}
@@ -0,0 +1,108 @@
+use rocket::Shutdown;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Polls the modification time of `path` every `interval`, calling `changed` whenever it
+/// changes, until Rocket shuts down or `changed` returns false.
+pub(crate) async fn watch_file<F>(
+    path: PathBuf,
+    interval: Duration,
+    mut shutdown: Shutdown,
+    mut changed: F,
+) where
+    F: FnMut() -> bool + Send,
+{
+    let modified = |p: &Path| fs::metadata(p).and_then(|m| m.modified()).ok();
+    let mut last_modified = modified(&path);
+
+    let mut interval = rocket::tokio::time::interval(interval);
+    loop {
+        rocket::tokio::select! {
+            _ = interval.tick() => {},
+            _ = &mut shutdown => break,
+        }
+
+        let now_modified = modified(&path);
+        if now_modified != last_modified {
+            last_modified = now_modified;
+            if !changed() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::tokio::sync::mpsc;
+    use rocket::tokio::time::{sleep, timeout};
+    use std::fs::File;
+    use std::time::UNIX_EPOCH;
+
+    fn touch(path: &Path, secs: u64) {
+        let file = File::options().write(true).open(path).unwrap();
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(secs))
+            .unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn calls_back_on_changes_until_told_to_stop() {
+        let path =
+            std::env::temp_dir().join(format!("rocket-gssapi-watch-change-{}", std::process::id()));
+        fs::write(&path, "keys").unwrap();
+        let shutdown = rocket::build().ignite().await.unwrap().shutdown();
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut changes = 0;
+        let watcher = rocket::tokio::spawn(watch_file(
+            path.clone(),
+            Duration::from_millis(10),
+            shutdown,
+            move || {
+                changes += 1;
+                tx.send(()).unwrap();
+                changes < 2
+            },
+        ));
+
+        sleep(Duration::from_millis(50)).await;
+        assert!(rx.try_recv().is_err());
+
+        touch(&path, 1_000_000);
+        assert!(timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .is_some());
+        sleep(Duration::from_millis(50)).await;
+        assert!(rx.try_recv().is_err());
+
+        // A keytab that disappears has changed as well
+        fs::remove_file(&path).unwrap();
+        assert!(timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .is_some());
+        assert!(timeout(Duration::from_secs(5), watcher).await.is_ok());
+    }
+
+    #[rocket::async_test]
+    async fn stops_on_shutdown() {
+        let path = std::env::temp_dir().join(format!(
+            "rocket-gssapi-watch-shutdown-{}",
+            std::process::id()
+        ));
+        let shutdown = rocket::build().ignite().await.unwrap().shutdown();
+        let watcher = rocket::tokio::spawn(watch_file(
+            path,
+            Duration::from_millis(10),
+            shutdown.clone(),
+            || panic!("the file doesn't change"),
+        ));
+
+        sleep(Duration::from_millis(20)).await;
+        shutdown.notify();
+        assert!(timeout(Duration::from_secs(5), watcher).await.is_ok());
+    }
+}